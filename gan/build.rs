@@ -0,0 +1,74 @@
+//! 扫描`src/comfy/`里`#[derive(ComfyNode)]`的结构, 生成`Inputs`的变体列表,
+//! 两个结构标了同一个`class_type`时构建失败
+
+use std::{collections::HashMap, env, fs, path::Path};
+
+use syn::{Attribute, Item, LitStr};
+
+fn main() {
+    let dir = Path::new("src/comfy");
+    println!("cargo:rerun-if-changed={}", dir.display());
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .expect("read src/comfy")
+        .map(|e| e.expect("read src/comfy").path())
+        .filter(|p| p.extension().is_some_and(|e| e == "rs"))
+        .collect();
+    files.sort();
+
+    let mut nodes = Vec::new();
+    let mut class_types: HashMap<String, String> = HashMap::new();
+    for file in files {
+        let src = fs::read_to_string(&file).expect("read node file");
+        // 语法错误留给rustc报
+        let Ok(ast) = syn::parse_file(&src) else {
+            continue;
+        };
+        for item in ast.items {
+            let Item::Struct(node) = item else {
+                continue;
+            };
+            if !derives_comfy_node(&node.attrs) {
+                continue;
+            }
+            let name = node.ident.to_string();
+            // 缺`class_type`留给derive报
+            if let Some(class_type) = class_type(&node.attrs) {
+                if let Some(prev) = class_types.insert(class_type.clone(), name.clone()) {
+                    panic!(
+                        "{}: class_type \"{class_type}\" already used by {prev}, {name} must differ",
+                        file.display()
+                    );
+                }
+            }
+            nodes.push(name);
+        }
+    }
+
+    let out = Path::new(&env::var("OUT_DIR").expect("OUT_DIR")).join("comfy_nodes.rs");
+    fs::write(out, format!("comfy_inputs!({});\n", nodes.join(", ")))
+        .expect("write comfy_nodes.rs");
+}
+
+fn derives_comfy_node(attrs: &[Attribute]) -> bool {
+    let mut found = false;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("derive")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            found |= meta.path.is_ident("ComfyNode");
+            Ok(())
+        });
+    }
+    found
+}
+
+fn class_type(attrs: &[Attribute]) -> Option<String> {
+    let mut class_type = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("comfy")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("class_type") {
+                class_type = Some(meta.value()?.parse::<LitStr>()?.value());
+            }
+            Ok(())
+        });
+    }
+    class_type
+}
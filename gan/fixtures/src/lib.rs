@@ -17,5 +17,12 @@ include_str_as_fn!(control_nets, "../data/control_nets.json");
 include_str_as_fn!(meikao, "../data/美考api.json");
 include_str_as_fn!(txt2img, "../data/文生图api.json");
 include_str_as_fn!(img2img, "../data/图生图api.json");
-include_str_as_fn!(class_names, "../data/class_names.json");
 include_str_as_fn!(preprocessors, "../data/preprocessors.json");
+include_str_as_fn!(object_info, "../data/object_info.json");
+include_str_as_fn!(txt2img_ui, "../data/美考文生图流程.json");
+include_str_as_fn!(txt2img_ui_new, "../data/美考文生图流程_新版界面.json");
+include_str_as_fn!(img2img_ui, "../data/美考图生图流程.json");
+include_str_as_fn!(meikao_ui, "../data/美考通用流程.json");
+include_str_as_fn!(fragment_post, "../data/后处理片段.json");
+include_str_as_fn!(txt2img_sdxl, "../data/SDXL文生图api.json");
+include_str_as_fn!(txt2img_split, "../data/分步采样api.json");
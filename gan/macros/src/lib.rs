@@ -1,47 +1,217 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, Data, DataEnum, DeriveInput, Fields};
+use quote::{format_ident, quote, ToTokens};
+use syn::{parse_macro_input, Attribute, Data, DataEnum, DeriveInput, Fields, LitStr};
 
-#[proc_macro_derive(FromNode)]
+/// 每个变体是`X(X)`, `X`用`#[derive(ComfyNode)]`声明comfy类,
+/// `#[comfy(other)]`标注的变体接收其它所有类的原始输入
+#[proc_macro_derive(FromNode, attributes(comfy))]
 pub fn derive_from_node(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as DeriveInput);
+    let name = &input.ident;
 
     let variants = if let Data::Enum(DataEnum { variants, .. }) = input.data {
         variants
     } else {
-        panic!("FromNode can only be applied to enums");
+        return compile_error(name, "FromNode can only be applied to enums");
     };
 
-    let from_impls = variants.into_iter().map(|variant| {
+    let mut class_arms = Vec::new();
+    let mut parse_ifs = Vec::new();
+    let mut class_names = Vec::new();
+    let mut other = quote! { None };
+    for variant in variants.into_iter() {
         let variant_name = &variant.ident;
-        let fields = match variant.fields {
-            Fields::Unnamed(fields) => fields.unnamed,
-            _ => panic!("Enum variants should be tuples"),
+        let attr = match comfy_attr(&variant.attrs) {
+            Ok(attr) => attr,
+            Err(e) => return e.to_compile_error().into(),
+        };
+        if let Some(ComfyAttr::Other) = attr {
+            class_arms.push(quote! {
+                #name::#variant_name(_) => None,
+            });
+            other = quote! {
+                Some(serde_path_to_error::deserialize(inputs).map(#name::#variant_name))
+            };
+            continue;
+        }
+        let field_type = match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+            _ => return compile_error(variant_name, "FromNode variants should be X(X)"),
         };
+        let class_type = quote! { <#field_type as crate::ComfyNode>::CLASS_TYPE };
 
-        let field_type = &fields.first().expect("Enum variant should have a field").ty;
+        class_arms.push(quote! {
+            #name::#variant_name(_) => Some(#class_type),
+        });
+        parse_ifs.push(quote! {
+            if class_type == #class_type {
+                return Some(serde_path_to_error::deserialize(inputs).map(#name::#variant_name));
+            }
+        });
+        class_names.push(quote! { (stringify!(#variant_name), #class_type) });
+    }
 
-        quote! {
-            impl From<&crate::Node> for #field_type {
-                fn from(value: &crate::Node) -> Self {
-                    match &value.inputs {
-                        Inputs::#variant_name(v) => v.clone(),
-                        _ => panic!(stringify!(#variant_name)),
-                    }
+    let expanded = quote! {
+        impl #name {
+            /// 变体对应的comfy类, `other`变体返回`None`
+            pub fn class_type(&self) -> Option<&'static str> {
+                match self {
+                    #(#class_arms)*
                 }
             }
+
+            /// 所有支持的(自己类, comfy类)
+            pub fn class_names() -> &'static [(&'static str, &'static str)] {
+                &[#(#class_names),*]
+            }
+
+            /// 所有支持的comfy类
+            pub fn class_types() -> Vec<&'static str> {
+                Self::class_names().iter().map(|(_, c)| *c).collect()
+            }
+
+            /// 按comfy类解析输入, 不支持的类返回`None`, 出错带字段路径
+            pub fn from_class_type(
+                class_type: &str,
+                inputs: serde_json::Value,
+            ) -> Option<Result<Self, serde_path_to_error::Error<serde_json::Error>>> {
+                #(#parse_ifs)*
+                #other
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+/// 结点输入用`#[comfy(class_type = "...")]`标注comfy类, 生成`ComfyNode`,
+/// 和`Inputs`同名变体的互相转换, 以及`Node`的`x()`/`x_mut()`/`try_x()`/`try_x_mut()`
+#[proc_macro_derive(ComfyNode, attributes(comfy))]
+pub fn derive_comfy_node(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    let name = &input.ident;
+    if !matches!(input.data, Data::Struct(_)) {
+        return compile_error(name, "ComfyNode can only be applied to structs");
+    }
+    let class_type = match comfy_attr(&input.attrs) {
+        Ok(Some(ComfyAttr::ClassType(class_type))) => class_type,
+        Ok(_) => return compile_error(name, "ComfyNode needs #[comfy(class_type = \"...\")]"),
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let snake = snake_case(&name.to_string());
+    let get = format_ident!("{snake}");
+    let get_mut = format_ident!("{snake}_mut");
+    let try_get = format_ident!("try_{snake}");
+    let try_get_mut = format_ident!("try_{snake}_mut");
+    let type_error = quote! {
+        crate::AppError::NodeType {
+            title: self.meta.title.clone(),
+            class_type: self.class_type.clone(),
+            expected: stringify!(#name),
         }
-    });
+    };
 
     let expanded = quote! {
-        #(#from_impls)*
+        impl crate::ComfyNode for #name {
+            const CLASS_TYPE: &'static str = #class_type;
+        }
+
+        impl From<#name> for crate::Inputs {
+            fn from(value: #name) -> Self {
+                crate::Inputs::#name(value)
+            }
+        }
+
+        impl TryFrom<&crate::Node> for #name {
+            type Error = crate::AppError;
+
+            fn try_from(value: &crate::Node) -> crate::AppResult<Self> {
+                value.#try_get().cloned()
+            }
+        }
+
+        impl crate::Node {
+            pub fn #get(&self) -> &#name {
+                match &self.inputs {
+                    crate::Inputs::#name(v) => v,
+                    _ => panic!("{} not {}", self.meta.title, stringify!(#name)),
+                }
+            }
+
+            pub fn #get_mut(&mut self) -> &mut #name {
+                match &mut self.inputs {
+                    crate::Inputs::#name(v) => v,
+                    _ => panic!("{} not {}", self.meta.title, stringify!(#name)),
+                }
+            }
+
+            pub fn #try_get(&self) -> crate::AppResult<&#name> {
+                match &self.inputs {
+                    crate::Inputs::#name(v) => Ok(v),
+                    _ => Err(#type_error),
+                }
+            }
+
+            pub fn #try_get_mut(&mut self) -> crate::AppResult<&mut #name> {
+                match &mut self.inputs {
+                    crate::Inputs::#name(v) => Ok(v),
+                    _ => Err(#type_error),
+                }
+            }
+        }
     };
 
     TokenStream::from(expanded)
 }
 
+/// 同`paste`的`:snake`, `IPAdapterApply` => `i_p_adapter_apply`
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    let mut prev = '_';
+    for ch in name.chars() {
+        if ch.is_uppercase() && prev != '_' {
+            snake.push('_');
+        }
+        snake.push(ch);
+        prev = ch;
+    }
+    snake.to_lowercase()
+}
+
+enum ComfyAttr {
+    /// `#[comfy(class_type = "...")]`
+    ClassType(LitStr),
+    /// `#[comfy(other)]`
+    Other,
+}
+
+/// 写错的`#[comfy(...)]`返回带位置的错误
+fn comfy_attr(attrs: &[Attribute]) -> syn::Result<Option<ComfyAttr>> {
+    let mut comfy = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("comfy")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("class_type") {
+                comfy = Some(ComfyAttr::ClassType(meta.value()?.parse::<LitStr>()?));
+                Ok(())
+            } else if meta.path.is_ident("other") {
+                comfy = Some(ComfyAttr::Other);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported comfy attribute"))
+            }
+        })?;
+    }
+    Ok(comfy)
+}
+
+fn compile_error<T: ToTokens>(tokens: T, msg: &str) -> TokenStream {
+    syn::Error::new_spanned(tokens, msg)
+        .to_compile_error()
+        .into()
+}
+
 #[proc_macro_derive(FromValue)]
 pub fn derive_from_value(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as DeriveInput);
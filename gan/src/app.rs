@@ -1,16 +1,94 @@
-use std::{ops::Deref, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    ops::Deref,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use anyhow::Context;
 use futures_util::StreamExt;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use tracing::{debug, info, warn};
+use serde_json::Value;
+use tokio::{
+    net::TcpStream,
+    signal,
+    sync::mpsc::{self, UnboundedSender},
+    task::JoinSet,
+    time::sleep,
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, error, info, trace, warn};
 use uuid::Uuid;
 
-use crate::{AppCfg, AppResult, AppState, AutoCfg, Comfy, Generator, StatusMsg, Workflow, WsMsg};
+use crate::{
+    is_ui_json, AppCfg, AppError, AppResult, AppState, AutoCfg, Comfy, ExecutedMsg, ExecutingMsg,
+    ExecutionErrorMsg, ExecutionInterruptedMsg, GenRecord, Generator, GraphFormat, ImageRef,
+    ObjectInfo, OutputStore, Preflight, ProgressMsg, QueueItem, StatusMsg, Uploader, Workflow,
+    WorkflowDiff, WsEvent,
+};
+
+/// 断线重连间隔
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+/// 一台服务器连续这么多轮生图出错就停用
+const ROUND_RETRIES: u32 = 5;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// ws任务转给主循环的事件
+enum ConnEvent {
+    Ws(WsEvent),
+    /// 断线重连成功
+    Reconnected,
+    /// 生图出错退避到期, 重试
+    Retry,
+}
+
+/// prompt执行状态
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum PromptState {
+    #[default]
+    Queued,
+    Running,
+    Success,
+    Failed(String),
+    Interrupted,
+}
+
+/// 单个prompt的执行进度
+#[derive(Debug, Clone, Default)]
+pub struct PromptProgress {
+    /// 提交到的comfy服务器
+    pub server: String,
+    /// 出图参数
+    pub record: GenRecord,
+    pub state: PromptState,
+    /// 正在执行的结点
+    pub node: Option<String>,
+    /// 结点内进度 value/max
+    pub value: u32,
+    pub max: u32,
+    /// 用了缓存的结点
+    pub cached: Vec<String>,
+    /// 已输出的图片
+    pub images: Vec<ImageRef>,
+}
+
+impl PromptProgress {
+    fn new(server: &str, record: GenRecord) -> Self {
+        Self {
+            server: server.to_owned(),
+            record,
+            ..Default::default()
+        }
+    }
+}
 
 pub struct State {
     pub db: AppState,
     pub cfg: AppCfg,
+    /// 本进程提交的prompt, prompt_id => 进度
+    prompts: Mutex<HashMap<String, PromptProgress>>,
 }
 
 impl State {
@@ -63,63 +141,279 @@ impl App {
     pub fn new(cfg_file: &str, clean: bool) -> AppResult<App> {
         let cfg = AppCfg::from_file(cfg_file)?;
         let db_file = cfg_file.replace(".toml", ".db");
-        let db = AppState::from_file(&db_file)?;
+        Self::from_cfg(cfg, &db_file, clean)
+    }
+
+    pub fn from_cfg(cfg: AppCfg, db_file: &str, clean: bool) -> AppResult<App> {
+        let db = AppState::from_file(db_file)?;
         if clean {
             warn!("clean db");
             db.clean();
         }
-        let app_state = State { db, cfg };
+        let app_state = State {
+            db,
+            cfg,
+            prompts: Default::default(),
+        };
         Ok(App(Arc::new(app_state)))
     }
 
     pub async fn run(&self) -> AppResult<()> {
-        let client_id = Uuid::new_v4().to_string();
-        let api = self.cfg.comfy_api.as_str();
-        let ws_url = format!("ws://{api}/ws?clientId={client_id}");
-        info!("ws_url: {ws_url}");
-        let gen = Generator::new();
-
-        let (ws_stream, _) = connect_async(ws_url).await?;
-        let (_, mut read) = ws_stream.split();
+        self.run_until(shutdown_signal()).await
+    }
 
-        let api = Comfy::new(api, client_id.as_str());
+    /// `shutdown`完成时停止提交并保存db, 按`interrupt_on_exit`清理服务器上本进程的prompt
+    pub async fn run_until(&self, shutdown: impl Future<Output = ()>) -> AppResult<()> {
+        let client_id = Uuid::new_v4().to_string();
         let cfg_idx = self.get_cfg_idx();
         let auto_cfg = self
             .get_auto_cfg(cfg_idx)
             .context(format!("get auto_cfg {cfg_idx}"))?;
         let total_idx = self.get_total_idx();
         info!("auto_cfg{cfg_idx} {auto_cfg}, total_idx={total_idx}");
-        let mut ac = AutoCfg::from_file(auto_cfg)?;
-        while let Some(msg) = read.next().await {
-            let msg = msg?;
+        let mut runner = Runner {
+            app: self,
+            servers: Vec::new(),
+            gen: Generator::new(),
+            ac: AutoCfg::from_file(auto_cfg)?,
+            uploader: Uploader::new(),
+            store: self.cfg.output_dir.as_deref().map(OutputStore::new),
+            done: false,
+        };
+
+        //每台服务器一个ws任务, 消息汇总到主循环处理
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut tasks = JoinSet::new();
+        for (idx, api) in self.cfg.comfy_api.iter().enumerate() {
+            let comfy = Comfy::new(api, client_id.as_str());
+            //可选值不对的配置跑几小时才报错, 开跑前先检查
+            let pf = Preflight::from_api(&comfy).await?;
+            self.preflight(&pf, api)?;
+            let ws_url = format!("ws://{api}/ws?clientId={client_id}");
+            info!("ws_url: {ws_url}");
+            //首次连不上直接报错, 之后断线一直重连
+            let (ws, _) = connect_async(ws_url.as_str()).await?;
+            tasks.spawn(ws_loop(idx, ws_url, ws, tx.clone()));
+            runner.servers.push(Server {
+                api: comfy,
+                pending: Default::default(),
+                info: pf.into_object_info(),
+                errors: 0,
+            });
+        }
+
+        tokio::pin!(shutdown);
+        let rst = loop {
+            let (idx, ev) = tokio::select! {
+                msg = rx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break Ok(()),
+                },
+                _ = &mut shutdown => {
+                    warn!("shutdown, stop queueing");
+                    runner.done = true;
+                    if self.cfg.interrupt_on_exit {
+                        runner.cancel_all().await;
+                    }
+                    break Ok(());
+                }
+            };
+            let rst = match ev {
+                ConnEvent::Ws(ev) => runner.on_event(idx, ev).await,
+                ConnEvent::Retry => runner.retry(idx).await,
+                ConnEvent::Reconnected => {
+                    if let Err(e) = runner.resync(idx).await {
+                        warn!("resync error: {e:?}");
+                    }
+                    Ok(())
+                }
+            };
+            //一台服务器出错不影响其它的, 退避后重试
+            if let Err(e) = rst {
+                match runner.on_round_error(idx, e) {
+                    Some(backoff) => {
+                        let tx = tx.clone();
+                        tasks.spawn(async move {
+                            sleep(backoff).await;
+                            let _ = tx.send((idx, ConnEvent::Retry));
+                        });
+                    }
+                    None if runner.servers.iter().all(|s| s.errors > ROUND_RETRIES) => {
+                        break Err("all servers failed".to_owned().into());
+                    }
+                    None => {}
+                }
+            }
+            if runner.done {
+                break Ok(());
+            }
+        };
+        tasks.abort_all();
+        self.save_db();
+
+        rst
+    }
+
+    /// 只检查配置, `object_info`为缓存文件时不连服务器, `save`保存服务器的`/object_info`
+    pub async fn check(&self, object_info: Option<&str>, save: Option<&str>) -> AppResult<()> {
+        if let Some(object_info) = object_info {
+            return self.preflight(&Preflight::from_file(object_info)?, object_info);
+        }
+        for api in self.cfg.comfy_api.iter() {
+            let pf = Preflight::from_api(&Comfy::new(api, "")).await?;
+            if let Some(save) = save {
+                std::fs::write(save, serde_json::to_string_pretty(pf.object_info())?)?;
+                info!("{api} object_info saved to {save}");
+            }
+            self.preflight(&pf, api)?;
+        }
+        Ok(())
+    }
+
+    /// 界面保存的流程转成api格式, `object_info`为缓存文件时不连服务器
+    pub async fn convert(&self, ui: &str, out: &str, object_info: Option<&str>) -> AppResult<()> {
+        let info = self.object_info(object_info).await?;
+        let wf = Workflow::from_ui_json(&std::fs::read_to_string(ui)?, &info)?;
+        std::fs::write(out, serde_json::to_string_pretty(&wf.to_json()?)?)?;
+        info!("{ui} converted to {out}");
+        Ok(())
+    }
+
+    /// 按结点标题比较两个流程, 界面格式的才用到`object_info`
+    pub async fn diff(&self, old: &str, new: &str, object_info: Option<&str>) -> AppResult<String> {
+        let (old, new) = (std::fs::read_to_string(old)?, std::fs::read_to_string(new)?);
+        let info = match is_ui_json(&old) || is_ui_json(&new) {
+            true => self.object_info(object_info).await?,
+            false => ObjectInfo::default(),
+        };
+        let diff = WorkflowDiff::from_json(&old, &new, &info)?;
+        if diff.is_empty() {
+            return Ok("no changes".to_owned());
+        }
+        Ok(diff.to_string())
+    }
+
+    /// 导出流程图, 接入当前auto_cfg的片段, 高亮它配置到的结点
+    pub async fn graph(
+        &self,
+        workflow: &str,
+        format: GraphFormat,
+        object_info: Option<&str>,
+    ) -> AppResult<String> {
+        let json = std::fs::read_to_string(workflow)?;
+        let mut wf = match is_ui_json(&json) {
+            true => Workflow::from_ui_json(&json, &self.object_info(object_info).await?)?,
+            false => Workflow::from_json(&json)?,
+        };
+        let ac = match self.get_auto_cfg(self.get_cfg_idx()) {
+            Some(auto_cfg) => Some(AutoCfg::from_file(auto_cfg)?),
+            None => None,
+        };
+        let touched = match &ac {
+            Some(ac) => {
+                for fragment in ac.fragments.iter() {
+                    wf.attach_cfg(fragment)?;
+                }
+                ac.titles()
+            }
+            None => Vec::new(),
+        };
+        Ok(wf.to_graph(format, &touched))
+    }
+
+    /// 缓存文件或第一台服务器的`/object_info`
+    async fn object_info(&self, object_info: Option<&str>) -> AppResult<ObjectInfo> {
+        match object_info {
+            Some(object_info) => Ok(Preflight::from_file(object_info)?.into_object_info()),
+            None => {
+                let api = self.cfg.comfy_api.first().context("no comfy_api")?;
+                Comfy::new(api, "").object_info().await
+            }
+        }
+    }
+
+    /// 检查所有auto_cfg, 报告按配置段输出
+    fn preflight(&self, pf: &Preflight, source: &str) -> AppResult<()> {
+        let mut failed = false;
+        for auto_cfg in self.cfg.auto_cfgs.iter() {
+            let report = pf.check(&AutoCfg::from_file(auto_cfg)?);
+            if report.is_ok() {
+                info!("preflight {source} {auto_cfg}: ok");
+            } else {
+                warn!("preflight {source} {auto_cfg}:\n{report}");
+                failed = true;
+            }
+        }
+        if failed {
+            return Err(format!("preflight {source} failed").into());
+        }
+        Ok(())
+    }
+
+    /// 跟踪中的prompt进度
+    pub fn prompt_progress(&self, prompt_id: &str) -> Option<PromptProgress> {
+        self.prompts.lock().unwrap().get(prompt_id).cloned()
+    }
+}
+
+/// Ctrl-C或SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            warn!("ctrl_c error: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                warn!("sigterm error: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => info!("got Ctrl-C"),
+        _ = terminate => info!("got SIGTERM"),
+    }
+}
+
+/// 读单个服务器的ws, 断线用同一个client_id重连, 指数退避
+async fn ws_loop(
+    idx: usize,
+    ws_url: String,
+    mut ws: WsStream,
+    tx: UnboundedSender<(usize, ConnEvent)>,
+) {
+    loop {
+        while let Some(msg) = ws.next().await {
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(e) => {
+                    warn!("comfy{idx} ws error: {e}");
+                    break;
+                }
+            };
             match msg {
                 Message::Text(text) => {
-                    debug!("text: {text}");
-                    let msg: WsMsg = serde_json::from_str(text.as_str())?;
-                    if msg.typ == "status" {
-                        let status: StatusMsg = serde_json::from_value(msg.data)?;
-                        let remaining = status.status.exec_info.queue_remaining;
-                        info!("remaining: {remaining}");
-                        if remaining == 0 {
-                            self.do_gen(&ac, &api, &gen, total_idx).await?;
-                            let total_idx = self.incr_total_idx();
-                            if total_idx >= ac.total {
-                                self.reset_total_idx();
-                                let cfg_idx = self.incr_cfg_idx();
-                                if let Some(nxt_ac) = self.get_auto_cfg(cfg_idx) {
-                                    ac = AutoCfg::from_file(nxt_ac)?;
-                                } else {
-                                    warn!("no more auto_cfg, exit");
-                                    self.save_db();
-                                    break;
-                                }
+                    debug!("comfy{idx} text: {text}");
+                    match serde_json::from_str::<WsEvent>(text.as_str()) {
+                        Ok(ev) => {
+                            if tx.send((idx, ConnEvent::Ws(ev))).is_err() {
+                                return;
                             }
-                            self.save_db();
                         }
+                        Err(e) => warn!("bad ws msg {text}: {e}"),
                     }
                 }
                 Message::Close(_) => {
-                    info!("comfy ws closed");
+                    info!("comfy{idx} ws closed");
                     break;
                 }
                 _ => {
@@ -128,26 +422,792 @@ impl App {
             }
         }
 
+        let mut backoff = RECONNECT_MIN;
+        ws = loop {
+            warn!("comfy{idx} reconnect in {backoff:?}");
+            sleep(backoff).await;
+            match connect_async(ws_url.as_str()).await {
+                Ok((ws, _)) => {
+                    info!("comfy{idx} ws reconnected");
+                    break ws;
+                }
+                Err(e) => {
+                    warn!("comfy{idx} reconnect error: {e}");
+                    backoff = (backoff * 2).min(RECONNECT_MAX);
+                }
+            }
+        };
+        if tx.send((idx, ConnEvent::Reconnected)).is_err() {
+            return;
+        }
+    }
+}
+
+/// 一台comfy服务器
+struct Server {
+    api: Comfy,
+    /// 未完成的prompt, prompt_id => prompt, 断线重连后补提交
+    pending: Mutex<HashMap<String, Value>>,
+    /// 界面格式的流程按服务器的结点定义转换
+    info: ObjectInfo,
+    /// 连续出错的生图轮数, 超过`ROUND_RETRIES`停用
+    errors: u32,
+}
+
+/// 一次`App::run`的上下文, 分发ws消息
+struct Runner<'a> {
+    app: &'a App,
+    servers: Vec<Server>,
+    gen: Generator,
+    ac: AutoCfg,
+    uploader: Uploader,
+    /// 出图下载目录
+    store: Option<OutputStore>,
+    /// 没有更多auto_cfg, 结束
+    done: bool,
+}
+
+impl Runner<'_> {
+    fn host(&self, srv: usize) -> &str {
+        self.servers[srv].api.host()
+    }
+
+    /// 退出时中断所有服务器上本进程的prompt
+    async fn cancel_all(&self) {
+        for srv in 0..self.servers.len() {
+            if let Err(e) = self.cancel(srv).await {
+                warn!("cancel {} error: {e:?}", self.host(srv));
+            }
+        }
+    }
+
+    /// 删除排队中的, 正在跑的是本进程的才按prompt_id`/interrupt`
+    async fn cancel(&self, srv: usize) -> AppResult<()> {
+        let api = &self.servers[srv].api;
+        let ours = |q: &&QueueItem| q.client_id() == Some(api.client_id());
+        let queue = api.queue().await?;
+        let pending: Vec<String> = queue
+            .queue_pending
+            .iter()
+            .filter(ours)
+            .map(|q| q.prompt_id.clone())
+            .collect();
+        if !pending.is_empty() {
+            api.delete_queue(&pending).await?;
+        }
+        //删除期间正在跑的可能已换成别人的, 中断前重新取一次, 按prompt_id中断, 之后再换也不会误中断
+        let running: Vec<String> = api
+            .queue()
+            .await?
+            .queue_running
+            .iter()
+            .filter(ours)
+            .map(|q| q.prompt_id.clone())
+            .collect();
+        for prompt_id in running.iter() {
+            api.interrupt(prompt_id).await?;
+        }
+        info!(
+            "{}: deleted {} pending, interrupted {}",
+            api.host(),
+            pending.len(),
+            running.len()
+        );
+        for prompt_id in pending.iter().chain(running.iter()) {
+            self.update(prompt_id, |p| p.state = PromptState::Interrupted);
+            self.untrack(srv, prompt_id);
+        }
         Ok(())
     }
 
-    async fn do_gen(
-        &self,
-        ac: &AutoCfg,
-        api: &Comfy,
-        gen: &Generator,
-        idx: usize,
-    ) -> AppResult<()> {
+    /// 重连后对比`/queue`, 服务器重启丢失的prompt重新提交.
+    /// 一个prompt出错只记日志, 不影响其它的; 拿不到`/queue`时只处理`/history`里已跑完的
+    async fn resync(&self, srv: usize) -> AppResult<()> {
+        let server = &self.servers[srv];
+        let queued: Option<HashSet<String>> = match server.api.queue().await {
+            Ok(queue) => Some(
+                queue
+                    .queue_running
+                    .iter()
+                    .chain(queue.queue_pending.iter())
+                    .map(|q| q.prompt_id.clone())
+                    .collect(),
+            ),
+            Err(e) => {
+                warn!("resync {}: queue error: {e:?}", server.api.host());
+                None
+            }
+        };
+        let lost: Vec<(String, Value)> = server
+            .pending
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| !queued.as_ref().is_some_and(|q| q.contains(id.as_str())))
+            .map(|(id, prompt)| (id.clone(), prompt.clone()))
+            .collect();
+        info!(
+            "resync {}: {:?} in queue, {} lost",
+            server.api.host(),
+            queued.as_ref().map(|q| q.len()),
+            lost.len()
+        );
+        for (prompt_id, prompt) in lost {
+            let history = match server.api.history_of(&prompt_id).await {
+                Ok(history) => history,
+                Err(e) => {
+                    warn!("resync {prompt_id}: history error: {e:?}");
+                    continue;
+                }
+            };
+            if let Some(item) = history {
+                //断线期间已跑完, 出错|中断的也在历史里, 旧版没有status
+                match item.status.filter(|s| s.status_str != "success") {
+                    Some(status) => {
+                        let reason = format!("history status {}", status.status_str);
+                        self.on_failed(srv, &prompt_id, reason);
+                    }
+                    None => self.on_success(srv, &prompt_id).await,
+                }
+                continue;
+            }
+            if queued.is_none() {
+                //不知道是否还在队列里, 等下次重连
+                continue;
+            }
+            server.pending.lock().unwrap().remove(&prompt_id);
+            let progress = self.app.prompts.lock().unwrap().remove(&prompt_id);
+            let Some(progress) = progress else {
+                continue;
+            };
+            let extra_pnginfo = progress.record.extra_pnginfo();
+            match server.api.queue_prompt_with(&prompt, extra_pnginfo).await {
+                Ok(queued) => {
+                    info!("requeued {prompt_id} => {}", queued.prompt_id);
+                    self.track(srv, &queued.prompt_id, prompt, progress.record);
+                }
+                Err(e) => warn!("requeue {prompt_id}: {e}"),
+            }
+        }
+        Ok(())
+    }
+
+    fn track(&self, srv: usize, prompt_id: &str, prompt: Value, record: GenRecord) {
+        self.servers[srv]
+            .pending
+            .lock()
+            .unwrap()
+            .insert(prompt_id.to_owned(), prompt);
+        self.app.prompts.lock().unwrap().insert(
+            prompt_id.to_owned(),
+            PromptProgress::new(self.host(srv), record),
+        );
+    }
+
+    /// prompt结束(成功|失败|中断), 不再需要补提交
+    fn untrack(&self, srv: usize, prompt_id: &str) {
+        self.servers[srv].pending.lock().unwrap().remove(prompt_id);
+    }
+
+    async fn on_event(&mut self, srv: usize, ev: WsEvent) -> AppResult<()> {
+        if let Some(prompt_id) = ev.prompt_id() {
+            if !self.app.prompts.lock().unwrap().contains_key(prompt_id) {
+                trace!("not our prompt {prompt_id}");
+                return Ok(());
+            }
+        }
+        match ev {
+            WsEvent::Status(msg) => self.on_status(srv, msg).await?,
+            WsEvent::ExecutionStart(msg) => {
+                self.update(&msg.prompt_id, |p| p.state = PromptState::Running);
+            }
+            WsEvent::ExecutionCached(msg) => {
+                self.update(&msg.prompt_id, |p| p.cached = msg.nodes.clone());
+            }
+            WsEvent::Executing(msg) => self.on_executing(srv, msg).await,
+            WsEvent::Progress(msg) => self.on_progress(msg),
+            WsEvent::Executed(msg) => self.on_executed(msg),
+            WsEvent::ExecutionError(msg) => self.on_error(srv, msg),
+            WsEvent::ExecutionInterrupted(msg) => self.on_interrupted(srv, msg),
+            WsEvent::ExecutionSuccess(msg) => self.on_success(srv, &msg.prompt_id).await,
+            WsEvent::Unknown(msg) => trace!("unhandled ws msg: {}", msg.typ),
+        }
+        Ok(())
+    }
+
+    /// 随机一轮参数提交到服务器`srv`
+    async fn do_gen(&self, srv: usize, idx: usize) -> AppResult<()> {
+        let ac = &self.ac;
         // 对每个流程, 随机参数, 生`ct_per_params`次, 即每次空闲跑`流程数*ct_per_params`个图
-        for wf in ac.workflows.iter() {
-            let mut wf = Workflow::from_file(wf.as_str())?;
-            gen.rand(&mut wf, ac, idx)?;
-            for _ in 0..ac.ct_per_params {
-                wf.set_seed(rand::random::<u32>() as i64)?;
-                let prompt = wf.to_json()?;
-                api.queue_prompt(&prompt).await;
+        //一个流程出错不影响其它流程, 都出错时报错, 否则没有新的状态消息会卡住
+        let mut failed = 0;
+        for wf_file in ac.workflows.iter() {
+            if let Err(e) = self.gen_workflow(srv, idx, wf_file).await {
+                warn!("{wf_file}: {e}");
+                failed += 1;
+            }
+        }
+        if failed == ac.workflows.len() {
+            let auto_cfg = self.app.get_auto_cfg(self.app.get_cfg_idx());
+            let auto_cfg = auto_cfg.unwrap_or_default();
+            return Err(format!("auto_cfg {auto_cfg}: all workflows failed").into());
+        }
+        Ok(())
+    }
+
+    /// 一个流程随机参数, 上传输入图, 换种子提交`ct_per_params`次
+    async fn gen_workflow(&self, srv: usize, idx: usize, wf_file: &str) -> AppResult<()> {
+        let (app, ac, server) = (self.app, &self.ac, &self.servers[srv]);
+        let api = &server.api;
+        let cfg_idx = app.get_cfg_idx();
+        let auto_cfg = app.get_auto_cfg(cfg_idx).unwrap_or_default();
+        let mut wf = Workflow::load(wf_file, &server.info)?;
+        let params = self.gen.rand(&mut wf, ac, idx)?;
+        self.uploader.upload_inputs(&mut wf, api).await?;
+        for _ in 0..ac.ct_per_params {
+            let seed = rand::random::<u32>() as i64;
+            wf.set_seed(seed)?;
+            let prompt = wf.to_json()?;
+            let record = GenRecord {
+                auto_cfg: auto_cfg.to_owned(),
+                workflow: wf_file.to_owned(),
+                cfg_idx,
+                total_idx: idx,
+                seed,
+                params: params.clone(),
+            };
+            match api.queue_prompt_with(&prompt, record.extra_pnginfo()).await {
+                Ok(queued) => {
+                    info!(
+                        "queued {} #{} to {}",
+                        queued.prompt_id,
+                        queued.number,
+                        api.host()
+                    );
+                    self.track(srv, &queued.prompt_id, prompt.clone(), record);
+                    app.db.update_server(api.host(), |s| s.queued += 1);
+                    for (id, ne) in queued.node_errors.iter() {
+                        warn!(
+                            "{}: node {id}({}) {:?}",
+                            queued.prompt_id, ne.class_type, ne.errors
+                        );
+                    }
+                }
+                Err(AppError::Rejected(rejected)) => {
+                    //同一流程只变种子, 后面的也会被拒
+                    warn!("{wf_file} rejected, skip: {rejected}");
+                    break;
+                }
+                Err(e) => return Err(e),
             }
         }
         Ok(())
     }
+
+    fn update(&self, prompt_id: &str, f: impl FnOnce(&mut PromptProgress)) {
+        if let Some(p) = self.app.prompts.lock().unwrap().get_mut(prompt_id) {
+            f(p);
+        }
+    }
+
+    /// 服务器`srv`空闲时生成下一轮
+    async fn on_status(&mut self, srv: usize, msg: StatusMsg) -> AppResult<()> {
+        let remaining = msg.status.exec_info.queue_remaining;
+        info!("{} remaining: {remaining}", self.host(srv));
+        if remaining > 0 {
+            return Ok(());
+        }
+        self.next_round(srv).await
+    }
+
+    /// 退避到期, 期间已提交成功的会有状态消息驱动下一轮
+    async fn retry(&mut self, srv: usize) -> AppResult<()> {
+        if !self.servers[srv].pending.lock().unwrap().is_empty() {
+            return Ok(());
+        }
+        self.next_round(srv).await
+    }
+
+    async fn next_round(&mut self, srv: usize) -> AppResult<()> {
+        if self.done || self.servers[srv].errors > ROUND_RETRIES {
+            return Ok(());
+        }
+        let app = self.app;
+        self.do_gen(srv, app.get_total_idx()).await?;
+        self.servers[srv].errors = 0;
+        app.db.update_server(self.host(srv), |s| s.rounds += 1);
+        let total_idx = app.incr_total_idx() + 1;
+        if total_idx >= self.ac.total {
+            app.reset_total_idx();
+            self.next_auto_cfg();
+        }
+        app.save_db();
+        Ok(())
+    }
+
+    /// 换下一个auto_cfg, 读不了的跳过, 没有了就结束
+    fn next_auto_cfg(&mut self) {
+        let app = self.app;
+        loop {
+            let cfg_idx = app.incr_cfg_idx() + 1;
+            let Some(nxt_ac) = app.get_auto_cfg(cfg_idx) else {
+                warn!("no more auto_cfg, exit");
+                self.done = true;
+                return;
+            };
+            info!("auto_cfg{cfg_idx} {nxt_ac}");
+            match AutoCfg::from_file(nxt_ac) {
+                Ok(ac) => {
+                    self.ac = ac;
+                    return;
+                }
+                Err(e) => warn!("auto_cfg{cfg_idx} {nxt_ac}: {e}, skip"),
+            }
+        }
+    }
+
+    /// 服务器`srv`这一轮出错, 返回重试前的退避时间, 连续出错太多次停用返回`None`
+    fn on_round_error(&mut self, srv: usize, e: AppError) -> Option<Duration> {
+        self.app
+            .db
+            .update_server(self.host(srv), |s| s.round_errors += 1);
+        let server = &mut self.servers[srv];
+        server.errors += 1;
+        let host = server.api.host();
+        if server.errors > ROUND_RETRIES {
+            error!(
+                "{host} failed {} rounds in a row, disabled: {e:?}",
+                server.errors
+            );
+            return None;
+        }
+        let backoff = (RECONNECT_MIN * 2u32.pow(server.errors - 1)).min(RECONNECT_MAX);
+        warn!("{host} round error, retry in {backoff:?}: {e:?}");
+        Some(backoff)
+    }
+
+    async fn on_executing(&self, srv: usize, msg: ExecutingMsg) {
+        let Some(prompt_id) = msg.prompt_id else {
+            return;
+        };
+        let finished = msg.node.is_none();
+        self.update(&prompt_id, |p| {
+            p.node = msg.node;
+            p.value = 0;
+            p.max = 0;
+        });
+        if finished {
+            //node为空, 执行结束(旧版没有execution_success)
+            self.on_success(srv, &prompt_id).await;
+        }
+    }
+
+    async fn on_success(&self, srv: usize, prompt_id: &str) {
+        let mut finished = false;
+        self.update(prompt_id, |p| {
+            if matches!(p.state, PromptState::Queued | PromptState::Running) {
+                p.state = PromptState::Success;
+                finished = true;
+            }
+        });
+        self.untrack(srv, prompt_id);
+        if finished {
+            self.app
+                .db
+                .update_server(self.host(srv), |s| s.success += 1);
+            if let Err(e) = self.download(srv, prompt_id).await {
+                warn!("{prompt_id} download error: {e:?}");
+            }
+        }
+    }
+
+    /// 通过`/history`找到prompt输出的图片, `/view`下载到本地
+    async fn download(&self, srv: usize, prompt_id: &str) -> AppResult<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let Some(record) = self.app.prompt_progress(prompt_id).map(|p| p.record) else {
+            return Ok(());
+        };
+        let api = &self.servers[srv].api;
+        let item = api
+            .history_of(prompt_id)
+            .await?
+            .context(format!("no history {prompt_id}"))?;
+        for output in item.outputs.values() {
+            for image in output.images.iter().filter(|i| i.typ == "output") {
+                let data = api.view(image).await?;
+                let path = store.save(prompt_id, image, &data, &record)?;
+                info!("{prompt_id} -> {}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    fn on_progress(&self, msg: ProgressMsg) {
+        let Some(prompt_id) = msg.prompt_id else {
+            return;
+        };
+        debug!("{prompt_id} {:?}: {}/{}", msg.node, msg.value, msg.max);
+        self.update(&prompt_id, |p| {
+            p.value = msg.value;
+            p.max = msg.max;
+        });
+    }
+
+    fn on_executed(&self, msg: ExecutedMsg) {
+        let images: Vec<&str> = msg
+            .output
+            .images
+            .iter()
+            .map(|i| i.filename.as_str())
+            .collect();
+        info!("{} node {} executed: {images:?}", msg.prompt_id, msg.node);
+        self.update(&msg.prompt_id, |p| p.images.extend(msg.output.images));
+    }
+
+    fn on_error(&self, srv: usize, msg: ExecutionErrorMsg) {
+        warn!(
+            "{} failed at {}({}): {}: {}",
+            msg.prompt_id, msg.node_id, msg.node_type, msg.exception_type, msg.exception_message
+        );
+        self.on_failed(srv, &msg.prompt_id, msg.exception_message);
+    }
+
+    fn on_failed(&self, srv: usize, prompt_id: &str, reason: String) {
+        self.update(prompt_id, |p| p.state = PromptState::Failed(reason));
+        self.untrack(srv, prompt_id);
+        self.app.db.update_server(self.host(srv), |s| s.failed += 1);
+    }
+
+    fn on_interrupted(&self, srv: usize, msg: ExecutionInterruptedMsg) {
+        warn!(
+            "{} interrupted at {}({})",
+            msg.prompt_id, msg.node_id, msg.node_type
+        );
+        self.update(&msg.prompt_id, |p| p.state = PromptState::Interrupted);
+        self.untrack(srv, &msg.prompt_id);
+    }
+}
+
+#[cfg(test)]
+mod app_tests {
+    use std::{fs, path::PathBuf};
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::MockComfy;
+
+    const RUN_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// 临时目录下写入流程和auto_cfg, 返回App
+    fn setup(name: &str, hosts: &[String], total: usize, output: bool) -> (App, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("gan_app_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let wf_file = dir.join("文生图api.json");
+        fs::write(&wf_file, fixtures::txt2img()).unwrap();
+        let auto_cfg = fixtures::test_auto_cfg()
+            .replace(
+                r#"workflows = ["文生图api.json"]"#,
+                &format!("workflows = [{:?}]", wf_file.to_str().unwrap()),
+            )
+            .replace("total = 600", &format!("total = {total}"));
+        let ac_file = dir.join("auto_cfg.toml");
+        fs::write(&ac_file, auto_cfg).unwrap();
+        let cfg = AppCfg {
+            comfy_api: hosts.to_vec(),
+            auto_cfgs: vec![ac_file.to_str().unwrap().to_owned()],
+            output_dir: output.then(|| dir.join("output").to_str().unwrap().to_owned()),
+            interrupt_on_exit: true,
+        };
+        let db_file = dir.join("app_cfg.db");
+        let app = App::from_cfg(cfg, db_file.to_str().unwrap(), true).unwrap();
+        (app, dir)
+    }
+
+    fn count_files(dir: &std::path::Path, ext: &str) -> usize {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return 0;
+        };
+        entries
+            .map(|e| e.unwrap().path())
+            .map(|p| match p.is_dir() {
+                true => count_files(&p, ext),
+                false => p.extension().is_some_and(|e| e == ext) as usize,
+            })
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn run_should_work() {
+        let mock = MockComfy::start().await.unwrap();
+        let (app, dir) = setup("run", &[mock.host()], 2, true);
+        timeout(RUN_TIMEOUT, app.run()).await.unwrap().unwrap();
+
+        // 2轮 * 1个流程 * ct_per_params=2
+        let received = mock.received();
+        assert_eq!(received.len(), 4);
+        assert!(received.iter().all(|p| p.prompt.is_object()));
+        //出图记录随prompt提交, 种子和提交的一致
+        let gan = &received[0].extra_data["extra_pnginfo"]["gan"];
+        assert_eq!(gan["total_idx"], 0);
+        assert_eq!(
+            gan["workflow"],
+            dir.join("文生图api.json").to_str().unwrap()
+        );
+        assert_eq!(gan["seed"], received[0].prompt["3"]["inputs"]["seed"]);
+        assert!(gan["params"]["positive"].is_string());
+        let stats = app.db.server_stats(&mock.host());
+        assert_eq!(stats.rounds, 2);
+        assert_eq!(stats.queued, 4);
+        // 第1轮跑完才开始第2轮, 第2轮提交后即退出
+        assert!(stats.success >= 2);
+        let first = app.prompt_progress(&received[0].prompt_id).unwrap();
+        assert_eq!(first.state, PromptState::Success);
+        assert_eq!(first.images.len(), 1);
+        let output = dir.join("output");
+        assert!(count_files(&output, "png") >= 2);
+        assert_eq!(count_files(&output, "png"), count_files(&output, "json"));
+    }
+
+    #[tokio::test]
+    async fn run_should_upload_local_images() {
+        let mock = MockComfy::start().await.unwrap();
+        let (app, dir) = setup("upload", &[mock.host()], 1, false);
+        //load_image配本地文件, 横图按文件名判断
+        let img = dir.join("imgs").join("land_1.png");
+        fs::create_dir_all(img.parent().unwrap()).unwrap();
+        fs::write(&img, b"png").unwrap();
+        let ac_file = dir.join("auto_cfg.toml");
+        let auto_cfg = fs::read_to_string(&ac_file).unwrap().replace(
+            "images = [",
+            &format!("images = [{:?},", img.to_str().unwrap()),
+        );
+        fs::write(&ac_file, auto_cfg).unwrap();
+        timeout(RUN_TIMEOUT, app.run()).await.unwrap().unwrap();
+
+        let uploads = mock.uploads();
+        assert_eq!(uploads.len(), 1);
+        assert!(uploads[0].starts_with("land_1_"));
+        let received = mock.received();
+        assert_eq!(received.len(), 2);
+        for p in received.iter() {
+            assert_eq!(p.prompt["15"]["inputs"]["image"], uploads[0].as_str());
+            assert_eq!(p.prompt["207"]["inputs"]["filename_prefix"], "land_1");
+            //横图交换宽高
+            let latent = &p.prompt["254"]["inputs"];
+            assert_eq!(
+                (latent["width"].as_u64(), latent["height"].as_u64()),
+                (Some(1365), Some(1024))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn run_should_accept_ui_workflow() {
+        let mock = MockComfy::start().await.unwrap();
+        let (app, dir) = setup("ui", &[mock.host()], 1, false);
+        //流程文件换成界面保存的格式, 用服务器的object_info转换
+        let ui_file = dir.join("文生图api.json");
+        fs::write(&ui_file, fixtures::txt2img_ui()).unwrap();
+        timeout(RUN_TIMEOUT, app.run()).await.unwrap().unwrap();
+        let received = mock.received();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].prompt["3"]["class_type"], "KSampler");
+
+        let out = dir.join("out.json");
+        let out = out.to_str().unwrap();
+        app.convert(ui_file.to_str().unwrap(), out, None)
+            .await
+            .unwrap();
+        let wf = Workflow::from_file(out).unwrap();
+        assert_eq!(wf.nodes().count(), 24);
+    }
+
+    #[tokio::test]
+    async fn run_should_skip_bad_workflow() {
+        let mock = MockComfy::start().await.unwrap();
+        let (app, dir) = setup("skip", &[mock.host()], 1, false);
+        //KSampler标题给了VAEDecode结点, 随机参数出错, 跳过它跑下一个流程
+        let mut bad: Value = serde_json::from_str(fixtures::txt2img()).unwrap();
+        bad["3"]["_meta"]["title"] = "Sampler".into();
+        bad["35"]["_meta"]["title"] = "KSampler".into();
+        let bad_file = dir.join("bad.json");
+        fs::write(&bad_file, bad.to_string()).unwrap();
+        let ac_file = dir.join("auto_cfg.toml");
+        let good_file = dir.join("文生图api.json");
+        let (bad_file, good_file) = (bad_file.to_str().unwrap(), good_file.to_str().unwrap());
+        let auto_cfg = fs::read_to_string(&ac_file).unwrap().replace(
+            &format!("workflows = [{good_file:?}]"),
+            &format!("workflows = [{bad_file:?}, {good_file:?}]"),
+        );
+        fs::write(&ac_file, auto_cfg).unwrap();
+        timeout(RUN_TIMEOUT, app.run()).await.unwrap().unwrap();
+        let received = mock.received();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].prompt["3"]["_meta"]["title"], "KSampler");
+    }
+
+    #[tokio::test]
+    async fn run_should_retry_failed_round() {
+        let mock = MockComfy::start().await.unwrap();
+        mock.error_next();
+        let (app, dir) = setup("retry", &[mock.host()], 1, false);
+        timeout(RUN_TIMEOUT, app.run()).await.unwrap().unwrap();
+
+        //第1次提交500, 这轮出错, 退避后重试整轮
+        assert_eq!(mock.received().len(), 2);
+        let stats = app.db.server_stats(&mock.host());
+        assert_eq!(stats.round_errors, 1);
+        assert_eq!(stats.rounds, 1);
+        assert!(dir.join("app_cfg.db").exists());
+    }
+
+    #[tokio::test]
+    async fn run_multi_servers_should_work() {
+        let (mock1, mock2) = (
+            MockComfy::start().await.unwrap(),
+            MockComfy::start().await.unwrap(),
+        );
+        let hosts = [mock1.host(), mock2.host()];
+        let (app, dir) = setup("multi", &hosts, 4, true);
+        timeout(RUN_TIMEOUT, app.run()).await.unwrap().unwrap();
+
+        assert_eq!(mock1.received().len() + mock2.received().len(), 8);
+        for host in hosts.iter() {
+            assert!(app.db.server_stats(host).rounds > 0);
+        }
+        //两台服务器的图片编号相同, 都要保存下来
+        let output = dir.join("output");
+        let mut saved = 0;
+        for p in mock1.received().iter().chain(mock2.received().iter()) {
+            let progress = app.prompt_progress(&p.prompt_id).unwrap();
+            if progress.state != PromptState::Success {
+                continue;
+            }
+            for image in progress.images.iter() {
+                let file = format!("{}_{}", p.prompt_id, image.filename);
+                assert!(output.join(&image.subfolder).join(file).exists());
+                saved += 1;
+            }
+        }
+        assert!(saved >= 4);
+        assert_eq!(count_files(&output, "png"), saved);
+        assert_eq!(count_files(&output, "json"), saved);
+    }
+
+    #[tokio::test]
+    async fn run_should_requeue_after_restart() {
+        let mock = MockComfy::start().await.unwrap();
+        mock.pause(true);
+        let (app, _) = setup("restart", &[mock.host()], 3, false);
+        let restart = async {
+            received(&mock, 2).await;
+            mock.restart();
+            mock.pause(false);
+        };
+        let (rst, _) = timeout(RUN_TIMEOUT, async { tokio::join!(app.run(), restart) })
+            .await
+            .unwrap();
+        rst.unwrap();
+
+        // 第1轮2个, 重启后补提交2个, 第2,3轮各2个
+        let received = mock.received();
+        assert_eq!(received.len(), 8);
+        for requeued in received[2..4].iter() {
+            assert!(received[..2].iter().any(|p| p.prompt == requeued.prompt));
+            assert!(requeued.extra_data["extra_pnginfo"]["gan"].is_object());
+            let progress = app.prompt_progress(&requeued.prompt_id).unwrap();
+            assert_eq!(progress.state, PromptState::Success);
+        }
+        assert!(app.prompt_progress(&received[0].prompt_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn resync_should_fail_errored_prompt() {
+        let mock = MockComfy::start().await.unwrap();
+        mock.pause(true);
+        let (app, _) = setup("resync", &[mock.host()], 2, false);
+        //断线期间第1个出错, 第2个成功, 重连后从历史里得知
+        let blip = async {
+            received(&mock, 2).await;
+            mock.fail_next();
+            mock.disconnect();
+            mock.pause(false);
+        };
+        let (rst, _) = timeout(RUN_TIMEOUT, async { tokio::join!(app.run(), blip) })
+            .await
+            .unwrap();
+        rst.unwrap();
+
+        // 出错的不补提交, 第2轮2个
+        let received = mock.received();
+        assert_eq!(received.len(), 4);
+        let failed = app.prompt_progress(&received[0].prompt_id).unwrap();
+        assert_eq!(
+            failed.state,
+            PromptState::Failed("history status error".into())
+        );
+        let success = app.prompt_progress(&received[1].prompt_id).unwrap();
+        assert_eq!(success.state, PromptState::Success);
+        assert_eq!(app.db.server_stats(&mock.host()).failed, 1);
+    }
+
+    /// 等到服务器收到`n`个prompt
+    async fn received(mock: &MockComfy, n: usize) {
+        while mock.received().len() < n {
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_should_cancel_our_prompts() {
+        let mock = MockComfy::start().await.unwrap();
+        mock.pause(true);
+        let (app, dir) = setup("shutdown", &[mock.host()], 2, false);
+        let other = Comfy::new(&mock.host(), "other");
+        let shutdown = async {
+            received(&mock, 2).await;
+            let prompt = serde_json::json!({"1": {"class_type": "Image Save", "inputs": {}}});
+            other.queue_prompt(&prompt).await.unwrap().prompt_id
+        };
+        let (rst, other_id) = timeout(RUN_TIMEOUT, async {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let shutdown = async {
+                let _ = tx.send(shutdown.await);
+            };
+            (app.run_until(shutdown).await, rx.await.unwrap())
+        })
+        .await
+        .unwrap();
+        rst.unwrap();
+
+        // 本进程的第1个在跑被中断, 第2个从队列删除, 别人的不动
+        let received = mock.received();
+        assert_eq!(received.len(), 3);
+        assert!(mock.interrupted());
+        let left: Vec<String> = mock.running().into_iter().chain(mock.pending()).collect();
+        assert_eq!(left, vec![other_id]);
+        for p in received[..2].iter() {
+            let progress = app.prompt_progress(&p.prompt_id).unwrap();
+            assert_eq!(progress.state, PromptState::Interrupted);
+        }
+        assert!(dir.join("app_cfg.db").exists());
+    }
+
+    #[tokio::test]
+    async fn run_should_track_errors() {
+        let mock = MockComfy::start().await.unwrap();
+        mock.fail_next();
+        let (app, _) = setup("error", &[mock.host()], 2, false);
+        timeout(RUN_TIMEOUT, app.run()).await.unwrap().unwrap();
+
+        let received = mock.received();
+        let failed = app.prompt_progress(&received[0].prompt_id).unwrap();
+        assert_eq!(failed.state, PromptState::Failed("mock error".into()));
+        let stats = app.db.server_stats(&mock.host());
+        assert_eq!(stats.failed, 1);
+    }
 }
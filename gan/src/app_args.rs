@@ -1,4 +1,6 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use crate::GraphFormat;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -8,6 +10,62 @@ pub struct AppArgs {
 
     #[arg(long)]
     pub clean: bool,
+
+    /// 不指定时自动出图
+    #[command(subcommand)]
+    pub cmd: Option<AppCmd>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AppCmd {
+    /// 用`/object_info`检查auto_cfg里的可选值
+    Check {
+        /// 缓存的object_info json, 指定时不连服务器
+        #[arg(long)]
+        object_info: Option<String>,
+
+        /// 保存服务器的object_info, 供离线检查
+        #[arg(long)]
+        save: Option<String>,
+    },
+    /// 界面保存的流程转成api格式
+    Convert {
+        /// 界面保存的流程json
+        ui: String,
+
+        /// 输出的api格式json
+        out: String,
+
+        /// 缓存的object_info json, 指定时不连服务器
+        #[arg(long)]
+        object_info: Option<String>,
+    },
+    /// 按结点标题比较两个流程
+    Diff {
+        old: String,
+
+        new: String,
+
+        /// 缓存的object_info json, 界面格式的流程才用到
+        #[arg(long)]
+        object_info: Option<String>,
+    },
+    /// 导出流程图, 高亮当前auto_cfg配置到的结点
+    Graph {
+        workflow: String,
+
+        /// dot|mermaid
+        #[arg(long, default_value = "dot")]
+        format: GraphFormat,
+
+        /// 输出文件, 不指定时打印
+        #[arg(long)]
+        out: Option<String>,
+
+        /// 缓存的object_info json, 界面格式的流程才用到
+        #[arg(long)]
+        object_info: Option<String>,
+    },
 }
 
 impl Default for AppArgs {
@@ -1,11 +1,21 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::{AppResult, IdxControlNet};
+use crate::{
+    AppResult, FragmentCfg, IdxControlNet, NODE_CANNY_PREPROCESSOR, NODE_CROP_IMAGE,
+    NODE_EMPTY_LATENT, NODE_IMAGE_FILTER, NODE_IMAGE_PREPROCESSOR, NODE_IMAGE_SCALESIDE,
+    NODE_KSAMPLER, NODE_LINEARTSTANDARD_PREPROCESSOR, NODE_LINEART_PREPROCESSOR, NODE_LOAD_IMAGE,
+    NODE_REPEAT_LATENT, NODE_TEXT_CONCAT, NODE_TEXT_STRING, NODE_TILE_PREPROCESSOR,
+    NODE_VAE_DECODE,
+};
 
 /// 应用状态
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,6 +30,24 @@ pub struct AppState {
     /// 当前配置跑到第几个循环 [0, total)
     #[serde(default)]
     pub total_idx: AtomicUsize,
+
+    /// 每个comfy服务器的出图统计 host => 统计
+    #[serde(default)]
+    pub servers: Mutex<HashMap<String, ServerStats>>,
+}
+
+/// 单个comfy服务器的出图统计
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerStats {
+    /// 空闲时分到的生图轮数
+    pub rounds: usize,
+    /// 提交的prompt数
+    pub queued: usize,
+    pub success: usize,
+    pub failed: usize,
+    /// 出错的生图轮数
+    #[serde(default)]
+    pub round_errors: usize,
 }
 impl AppState {
     fn from_json(toml_str: &str) -> AppResult<Self> {
@@ -36,6 +64,7 @@ impl AppState {
     pub fn clean(&self) {
         self.cfg_idx.store(0, Ordering::Relaxed);
         self.total_idx.store(0, Ordering::Relaxed);
+        self.servers.lock().unwrap().clear();
         let _ = self.save();
     }
 
@@ -68,12 +97,53 @@ impl AppState {
     pub fn set_cfg_idx(&self, idx: usize) {
         self.cfg_idx.store(idx, Ordering::Relaxed);
     }
+
+    pub fn server_stats(&self, host: &str) -> ServerStats {
+        self.servers
+            .lock()
+            .unwrap()
+            .get(host)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn update_server(&self, host: &str, f: impl FnOnce(&mut ServerStats)) {
+        f(self
+            .servers
+            .lock()
+            .unwrap()
+            .entry(host.to_owned())
+            .or_default());
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct AppCfg {
-    pub comfy_api: String,
+    /// comfy服务器, 单个"host:port"或列表
+    #[serde(deserialize_with = "one_or_many")]
+    pub comfy_api: Vec<String>,
     pub auto_cfgs: Vec<String>,
+    /// 出图下载到本地的目录, 不配置则不下载
+    pub output_dir: Option<String>,
+    /// Ctrl-C退出时中断本进程正在跑的prompt并清掉排队中的
+    #[serde(default)]
+    pub interrupt_on_exit: bool,
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
 }
 
 impl AppCfg {
@@ -134,6 +204,13 @@ pub struct AutoCfg {
 
     /// 图片后处理
     pub image_filter_after: Option<AImageFilter>,
+
+    /// 图片放大
+    pub image_upscale: Option<AImageUpscale>,
+
+    /// 片段, 随机参数前接入流程
+    #[serde(default)]
+    pub fragments: Vec<FragmentCfg>,
 }
 
 #[derive(Debug, Default, Clone, Deserialize)]
@@ -168,6 +245,7 @@ pub struct AEmptyImage {
     pub color: u32,
 }
 
+/// 流程里没有`title`结点时加在LoadImage后面
 #[derive(Debug, Deserialize)]
 pub struct AImageRembg {
     pub title: String,
@@ -175,6 +253,38 @@ pub struct AImageRembg {
     pub model_name: String,
 }
 
+/// "CR Upscale Image", 流程里没有`title`结点时加在VaeDecode后面
+#[derive(Debug, Deserialize)]
+pub struct AImageUpscale {
+    pub title: String,
+    pub switch: bool,
+    pub upscale_model: Vec<String>,
+    /// rescale|resize
+    pub mode: Vec<String>,
+    pub rescale_factor_min: f32,
+    pub rescale_factor_max: f32,
+    pub resize_width: u16,
+    /// lanczos|nearest|bilinear|bicubic
+    pub resampling_method: Vec<String>,
+    /// true|false
+    pub supersample: Vec<String>,
+    pub rounding_modulus: u16,
+    /// 放大后再采样一遍
+    #[serde(default)]
+    pub hires: Option<AHires>,
+}
+
+/// `[image_upscale.hires]`, 放大图VAEEncode后用`title`采样器重绘再VAEDecode,
+/// 流程里没有`title`结点时按`sampler`(KSampler/KSamplerAdvanced/SamplerCustom)建KSampler接在放大结点后面
+#[derive(Debug, Deserialize)]
+pub struct AHires {
+    pub title: String,
+    pub steps_min: u8,
+    pub steps_max: u8,
+    pub denoise_min: f32,
+    pub denoise_max: f32,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ALoadImage {
     pub title: String,
@@ -263,6 +373,26 @@ pub struct ASampler {
     pub denoise_max: f32,
     pub sampler_name: Vec<String>,
     pub scheduler: Vec<String>,
+    /// `title`是KSamplerAdvanced或SamplerCustom时的额外参数
+    #[serde(default)]
+    pub advanced: Option<ASamplerAdvanced>,
+}
+
+/// `[sampler.advanced]`, 空的不改流程里的值.
+/// SamplerCustom只用`add_noise`, 它的采样方法和步数改在上游的KSamplerSelect/BasicScheduler
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ASamplerAdvanced {
+    /// enable|disable
+    #[serde(default)]
+    pub add_noise: Vec<String>,
+    #[serde(default)]
+    pub start_at_step: Vec<u16>,
+    /// 10000到最后
+    #[serde(default)]
+    pub end_at_step: Vec<u16>,
+    /// enable|disable
+    #[serde(default)]
+    pub return_with_leftover_noise: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -398,6 +528,23 @@ pub struct AEfficient {
     pub clip_skip: Vec<i8>,
     pub ckpt_name: String,
     pub weight_interpretation: String,
+    /// 有`sdxl`时`title`是"Eff. Loader SDXL"结点, `ckpt_name`/`clip_skip`指基础模型
+    #[serde(default)]
+    pub sdxl: Option<ASdxl>,
+}
+
+/// `[efficient.sdxl]`, 只选SDXL的CN类型, `sampler.title`要是"KSampler SDXL (Eff.)"
+#[derive(Debug, Clone, Deserialize)]
+pub struct ASdxl {
+    /// 精炼模型, "None"不精炼
+    pub refiner_ckpt_name: Vec<String>,
+    pub refiner_clip_skip: Vec<i8>,
+    pub positive_ascore_min: f32,
+    pub positive_ascore_max: f32,
+    pub negative_ascore_min: f32,
+    pub negative_ascore_max: f32,
+    /// 最后几步用精炼模型, 0不精炼
+    pub refiner_steps: Vec<u8>,
 }
 
 impl AutoCfg {
@@ -408,6 +555,68 @@ impl AutoCfg {
     pub fn from_file(toml_file: &str) -> AppResult<Self> {
         Self::from_toml(fs::read_to_string(toml_file)?.as_str())
     }
+
+    /// 配置到的结点标题, 即`Generator`会改的结点
+    pub fn titles(&self) -> Vec<&str> {
+        let optional = [
+            self.load_image.as_ref().map(|c| &c.title),
+            self.image_filter.as_ref().map(|c| &c.title),
+            self.ctrlnet_stack.as_ref().map(|c| &c.title),
+            self.lora_stack.as_ref().map(|c| &c.title),
+            self.lora_stacker.as_ref().map(|c| &c.title),
+            self.empty_image.as_ref().map(|c| &c.title),
+            self.image_rembg.as_ref().map(|c| &c.title),
+            self.tagger.as_ref().map(|c| &c.title),
+            self.ip_adapter.as_ref().map(|c| &c.title),
+            self.image_filter_after.as_ref().map(|c| &c.title),
+            self.image_upscale.as_ref().map(|c| &c.title),
+            self.image_upscale
+                .as_ref()
+                .and_then(|c| c.hires.as_ref())
+                .map(|c| &c.title),
+        ];
+        let mut titles: Vec<&str> = [
+            &self.efficient.title,
+            &self.sampler.title,
+            &self.save_image.title,
+        ]
+        .into_iter()
+        .chain(optional.into_iter().flatten())
+        .map(String::as_str)
+        .collect();
+        //`Generator`按固定标题找的结点, 流程里有就会改
+        titles.extend([
+            NODE_IMAGE_SCALESIDE,
+            NODE_TEXT_STRING,
+            NODE_CROP_IMAGE,
+            NODE_REPEAT_LATENT,
+            NODE_EMPTY_LATENT,
+        ]);
+        if self.tagger.as_ref().is_some_and(|c| c.switch) {
+            titles.extend([NODE_IMAGE_FILTER, NODE_TEXT_CONCAT]);
+        }
+        if self.ip_adapter.as_ref().is_some_and(|c| c.switch) {
+            titles.push(NODE_KSAMPLER);
+        }
+        if self.image_rembg.as_ref().is_some_and(|c| c.switch) {
+            titles.push(NODE_LOAD_IMAGE);
+        }
+        if self.image_upscale.as_ref().is_some_and(|c| c.switch) {
+            titles.push(NODE_VAE_DECODE);
+        }
+        if self.ctrlnet_stack.as_ref().is_some_and(|c| c.switch()) {
+            titles.extend([
+                NODE_IMAGE_PREPROCESSOR,
+                NODE_CANNY_PREPROCESSOR,
+                NODE_LINEART_PREPROCESSOR,
+                NODE_LINEARTSTANDARD_PREPROCESSOR,
+                NODE_TILE_PREPROCESSOR,
+            ]);
+        }
+        let mut seen = HashSet::new();
+        titles.retain(|t| seen.insert(*t));
+        titles
+    }
 }
 
 #[cfg(test)]
@@ -438,10 +647,73 @@ mod ac_tests {
         assert_eq!(cfg.load_image.unwrap().title, NODE_LOAD_IMAGE);
     }
 
+    #[test]
+    fn titles_should_include_fixed_nodes() {
+        let mut cfg = AutoCfg::from_toml(test_auto_cfg()).unwrap();
+        let titles = cfg.titles();
+        assert!(titles.contains(&NODE_EMPTY_LATENT));
+        assert!(titles.contains(&NODE_CANNY_PREPROCESSOR));
+        assert!(!titles.contains(&NODE_VAE_DECODE));
+        assert_eq!(titles.iter().filter(|t| **t == NODE_KSAMPLER).count(), 1);
+
+        cfg.ctrlnet_stack = None;
+        cfg.image_upscale = Some(AImageUpscale {
+            title: "UpscaleImage".into(),
+            switch: true,
+            upscale_model: vec!["4x-UltraSharp.pth".into()],
+            mode: vec!["rescale".into()],
+            rescale_factor_min: 2.0,
+            rescale_factor_max: 2.0,
+            resize_width: 1024,
+            resampling_method: vec!["lanczos".into()],
+            supersample: vec!["true".into()],
+            rounding_modulus: 8,
+            hires: Some(AHires {
+                title: "HiresSampler".into(),
+                steps_min: 10,
+                steps_max: 10,
+                denoise_min: 0.4,
+                denoise_max: 0.4,
+            }),
+        });
+        let titles = cfg.titles();
+        assert!(!titles.contains(&NODE_CANNY_PREPROCESSOR));
+        assert!(titles.contains(&NODE_VAE_DECODE));
+        assert!(titles.contains(&"HiresSampler"));
+    }
+
     #[test]
     fn state_should_work() {
         let state = AppState::from_file("nonononononono").unwrap();
         assert_eq!(0, state.cfg_idx.load(Ordering::Relaxed));
         assert_eq!(0, state.total_idx.load(Ordering::Relaxed));
+        state.update_server("a:8188", |s| s.queued += 2);
+        assert_eq!(2, state.server_stats("a:8188").queued);
+        assert_eq!(0, state.server_stats("b:8188").queued);
+        let json = serde_json::to_string(&state).unwrap();
+        let state = AppState::from_json(&json).unwrap();
+        assert_eq!(2, state.server_stats("a:8188").queued);
+    }
+
+    #[test]
+    fn app_cfg_should_work() {
+        let cfg = AppCfg::from_toml(
+            r#"
+            auto_cfgs = ["a.toml"]
+            comfy_api = "127.0.0.1:8188"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.comfy_api, vec!["127.0.0.1:8188"]);
+        let cfg = AppCfg::from_toml(
+            r#"
+            auto_cfgs = ["a.toml"]
+            comfy_api = ["127.0.0.1:8188", "127.0.0.1:8189"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cfg.comfy_api.len(), 2);
+        assert!(cfg.output_dir.is_none());
+        assert!(!cfg.interrupt_on_exit);
     }
 }
@@ -1,20 +1,79 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
-use reqwest::Client;
-use serde::Deserialize;
-use serde_json::Value;
-use tracing::info;
+use reqwest::{multipart, Client, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::{info, trace};
+
+use crate::{AppError, AppResult};
 
 #[derive(Debug, Deserialize)]
 pub struct WsMsg {
     #[serde(rename = "type")]
     pub typ: String,
+    #[serde(default)]
     pub data: Value,
 }
 
+/// comfy ws消息, 按`type`分发`data`
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "WsMsg")]
+pub enum WsEvent {
+    Status(StatusMsg),
+    ExecutionStart(ExecutionStartMsg),
+    ExecutionCached(ExecutionCachedMsg),
+    Executing(ExecutingMsg),
+    Progress(ProgressMsg),
+    Executed(ExecutedMsg),
+    ExecutionError(ExecutionErrorMsg),
+    ExecutionInterrupted(ExecutionInterruptedMsg),
+    ExecutionSuccess(ExecutionSuccessMsg),
+    /// 插件等其它消息, 如`crystools.monitor`
+    Unknown(WsMsg),
+}
+
+impl TryFrom<WsMsg> for WsEvent {
+    type Error = serde_json::Error;
+
+    fn try_from(msg: WsMsg) -> Result<Self, Self::Error> {
+        use serde_json::from_value;
+        Ok(match msg.typ.as_str() {
+            "status" => WsEvent::Status(from_value(msg.data)?),
+            "execution_start" => WsEvent::ExecutionStart(from_value(msg.data)?),
+            "execution_cached" => WsEvent::ExecutionCached(from_value(msg.data)?),
+            "executing" => WsEvent::Executing(from_value(msg.data)?),
+            "progress" => WsEvent::Progress(from_value(msg.data)?),
+            "executed" => WsEvent::Executed(from_value(msg.data)?),
+            "execution_error" => WsEvent::ExecutionError(from_value(msg.data)?),
+            "execution_interrupted" => WsEvent::ExecutionInterrupted(from_value(msg.data)?),
+            "execution_success" => WsEvent::ExecutionSuccess(from_value(msg.data)?),
+            _ => WsEvent::Unknown(msg),
+        })
+    }
+}
+
+impl WsEvent {
+    /// 消息所属prompt, status等全局消息为None
+    pub fn prompt_id(&self) -> Option<&str> {
+        match self {
+            WsEvent::ExecutionStart(m) => Some(&m.prompt_id),
+            WsEvent::ExecutionCached(m) => Some(&m.prompt_id),
+            WsEvent::Executing(m) => m.prompt_id.as_deref(),
+            WsEvent::Progress(m) => m.prompt_id.as_deref(),
+            WsEvent::Executed(m) => Some(&m.prompt_id),
+            WsEvent::ExecutionError(m) => Some(&m.prompt_id),
+            WsEvent::ExecutionInterrupted(m) => Some(&m.prompt_id),
+            WsEvent::ExecutionSuccess(m) => Some(&m.prompt_id),
+            WsEvent::Status(_) | WsEvent::Unknown(_) => None,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct StatusMsg {
     pub status: Status,
+    /// 首次连接时带上client_id
+    pub sid: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +86,300 @@ pub struct ExecInfo {
     pub queue_remaining: usize,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ExecutionStartMsg {
+    pub prompt_id: String,
+}
+
+/// 输入未变, 直接用缓存结果的结点
+#[derive(Debug, Deserialize)]
+pub struct ExecutionCachedMsg {
+    pub prompt_id: String,
+    pub nodes: Vec<String>,
+}
+
+/// 开始执行`node`, `node`为None表示prompt执行完了
+#[derive(Debug, Deserialize)]
+pub struct ExecutingMsg {
+    pub node: Option<String>,
+    pub prompt_id: Option<String>,
+}
+
+/// 结点内进度, 如KSampler的步数
+#[derive(Debug, Deserialize)]
+pub struct ProgressMsg {
+    pub value: u32,
+    pub max: u32,
+    pub prompt_id: Option<String>,
+    pub node: Option<String>,
+}
+
+/// 输出结点执行完成
+#[derive(Debug, Deserialize)]
+pub struct ExecutedMsg {
+    pub node: String,
+    pub output: NodeOutput,
+    pub prompt_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExecutionErrorMsg {
+    pub prompt_id: String,
+    pub node_id: String,
+    pub node_type: String,
+    #[serde(default)]
+    pub executed: Vec<String>,
+    pub exception_message: String,
+    pub exception_type: String,
+    #[serde(default)]
+    pub traceback: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExecutionInterruptedMsg {
+    pub prompt_id: String,
+    pub node_id: String,
+    pub node_type: String,
+    #[serde(default)]
+    pub executed: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExecutionSuccessMsg {
+    pub prompt_id: String,
+}
+
+/// `/prompt`提交成功
+#[derive(Debug, Deserialize)]
+pub struct QueuedPrompt {
+    pub prompt_id: String,
+    /// 队列序号
+    pub number: i64,
+    /// 部分输出结点校验失败时仍会入队, node_id => 错误
+    #[serde(default)]
+    pub node_errors: HashMap<String, NodeError>,
+}
+
+/// `/prompt`校验失败(400)
+#[derive(Debug, Deserialize)]
+pub struct PromptRejected {
+    pub error: PromptError,
+    /// node_id => 错误
+    #[serde(default)]
+    pub node_errors: HashMap<String, NodeError>,
+}
+
+impl fmt::Display for PromptRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        let mut ids: Vec<&String> = self.node_errors.keys().collect();
+        ids.sort();
+        for id in ids {
+            let ne = &self.node_errors[id];
+            for e in ne.errors.iter() {
+                write!(f, "; {id}({}): {e}", ne.class_type)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeError {
+    pub errors: Vec<PromptError>,
+    #[serde(default)]
+    pub dependent_outputs: Vec<String>,
+    pub class_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PromptError {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub message: String,
+    #[serde(default)]
+    pub details: String,
+    #[serde(default)]
+    pub extra_info: Value,
+}
+
+impl fmt::Display for PromptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.details.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.message, self.details)
+        }
+    }
+}
+
+/// `/history` prompt_id => 执行记录
+pub type History = HashMap<String, HistoryItem>;
+
+/// `/object_info` comfy类 => 结点定义
+pub type ObjectInfo = HashMap<String, NodeInfo>;
+
+/// 一个prompt的执行记录
+#[derive(Debug, Deserialize)]
+pub struct HistoryItem {
+    /// [number, prompt_id, prompt, extra_data, outputs_to_execute]
+    pub prompt: Value,
+    /// node_id => 结点输出
+    #[serde(default)]
+    pub outputs: HashMap<String, NodeOutput>,
+    pub status: Option<HistoryStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryStatus {
+    /// success|error
+    pub status_str: String,
+    pub completed: bool,
+    #[serde(default)]
+    pub messages: Vec<Value>,
+}
+
+/// 结点输出, 只关心图片, 其它原样保留
+#[derive(Debug, Deserialize)]
+pub struct NodeOutput {
+    #[serde(default)]
+    pub images: Vec<ImageRef>,
+    #[serde(flatten)]
+    pub others: HashMap<String, Value>,
+}
+
+/// comfy上的图片, 用于`/view`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageRef {
+    pub filename: String,
+    #[serde(default)]
+    pub subfolder: String,
+    /// output|input|temp
+    #[serde(rename = "type")]
+    pub typ: String,
+}
+
+/// `/upload/image`上传结果
+#[derive(Debug, Deserialize)]
+pub struct UploadedImage {
+    /// 重名且不覆盖时comfy会改名
+    pub name: String,
+    #[serde(default)]
+    pub subfolder: String,
+    #[serde(rename = "type")]
+    pub typ: String,
+}
+
+/// `/queue`
+#[derive(Debug, Deserialize)]
+pub struct QueueInfo {
+    pub queue_running: Vec<QueueItem>,
+    pub queue_pending: Vec<QueueItem>,
+}
+
+/// 队列项, comfy返回的是数组[number, prompt_id, prompt, extra_data, outputs_to_execute]
+#[derive(Debug, Deserialize)]
+#[serde(from = "(i64, String, Value, Value, Vec<String>)")]
+pub struct QueueItem {
+    pub number: i64,
+    pub prompt_id: String,
+    pub prompt: Value,
+    pub extra_data: Value,
+    pub outputs_to_execute: Vec<String>,
+}
+
+impl QueueItem {
+    /// 提交者, 在extra_data里
+    pub fn client_id(&self) -> Option<&str> {
+        self.extra_data.get("client_id")?.as_str()
+    }
+}
+
+impl From<(i64, String, Value, Value, Vec<String>)> for QueueItem {
+    fn from(v: (i64, String, Value, Value, Vec<String>)) -> Self {
+        let (number, prompt_id, prompt, extra_data, outputs_to_execute) = v;
+        Self {
+            number,
+            prompt_id,
+            prompt,
+            extra_data,
+            outputs_to_execute,
+        }
+    }
+}
+
+/// `/system_stats`
+#[derive(Debug, Deserialize)]
+pub struct SystemStats {
+    pub system: SystemInfo,
+    #[serde(default)]
+    pub devices: Vec<DeviceStats>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SystemInfo {
+    pub os: String,
+    pub python_version: String,
+    #[serde(default)]
+    pub embedded_python: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceStats {
+    pub name: String,
+    /// cuda|mps|cpu
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub index: Option<i64>,
+    pub vram_total: u64,
+    pub vram_free: u64,
+    pub torch_vram_total: u64,
+    pub torch_vram_free: u64,
+}
+
+/// `/object_info`里的结点定义
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub input: NodeInfoInput,
+    #[serde(default)]
+    pub output: Vec<Value>,
+    #[serde(default)]
+    pub output_is_list: Vec<bool>,
+    #[serde(default)]
+    pub output_name: Vec<String>,
+    pub name: String,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub output_node: bool,
+}
+
+/// 输入定义 name => [type, options], type为数组时是可选值列表
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NodeInfoInput {
+    #[serde(default)]
+    pub required: Map<String, Value>,
+    #[serde(default)]
+    pub optional: Map<String, Value>,
+}
+
+impl NodeInfo {
+    /// 输入的可选值列表, 输入不存在或不是枚举返回`None`
+    pub fn choices(&self, input: &str) -> Option<Vec<&str>> {
+        let def = self
+            .input
+            .required
+            .get(input)
+            .or_else(|| self.input.optional.get(input))?;
+        let choices = def.get(0)?.as_array()?;
+        Some(choices.iter().filter_map(|c| c.as_str()).collect())
+    }
+}
+
 pub struct Comfy {
     host: String,
     uuid: String,
@@ -42,27 +395,178 @@ impl Comfy {
         }
     }
 
-    pub async fn queue_prompt(&self, prompt: &Value) {
-        let api = format!("http://{}/prompt", self.host);
-        let mut data = HashMap::new();
-        data.insert("prompt", prompt);
-        let client_id = self.uuid.clone().into();
-        data.insert("client_id", &client_id);
-        let json = serde_json::to_string(&data).unwrap();
-        info!("{json}");
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.uuid
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.host)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> AppResult<T> {
+        let url = self.url(path);
+        trace!("GET {url}");
+        let rsp = self.client.get(url).send().await?.error_for_status()?;
+        Ok(rsp.json().await?)
+    }
+
+    async fn post_json(&self, path: &str, body: &Value) -> AppResult<()> {
+        let url = self.url(path);
+        trace!("POST {url} {body}");
         self.client
-            .post(api)
-            .body(json)
+            .post(url)
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// 提交prompt, 校验失败返回`AppError::Rejected`
+    pub async fn queue_prompt(&self, prompt: &Value) -> AppResult<QueuedPrompt> {
+        self.post_prompt(json!({
+            "prompt": prompt,
+            "client_id": self.uuid,
+        }))
+        .await
+    }
+
+    /// 提交prompt, `extra_pnginfo`的每个键由保存结点写进png的文本块
+    pub async fn queue_prompt_with(
+        &self,
+        prompt: &Value,
+        extra_pnginfo: Value,
+    ) -> AppResult<QueuedPrompt> {
+        self.post_prompt(json!({
+            "prompt": prompt,
+            "client_id": self.uuid,
+            "extra_data": {"extra_pnginfo": extra_pnginfo},
+        }))
+        .await
+    }
+
+    async fn post_prompt(&self, data: Value) -> AppResult<QueuedPrompt> {
+        info!("{data}");
+        let rsp = self
+            .client
+            .post(self.url("/prompt"))
+            .json(&data)
             .send()
+            .await?;
+        if rsp.status() == StatusCode::BAD_REQUEST {
+            let rejected: PromptRejected = rsp.json().await?;
+            return Err(AppError::Rejected(Box::new(rejected)));
+        }
+        Ok(rsp.error_for_status()?.json().await?)
+    }
+
+    /// 最近`max_items`条执行记录, None为全部
+    pub async fn history(&self, max_items: Option<usize>) -> AppResult<History> {
+        match max_items {
+            Some(n) => self.get_json(&format!("/history?max_items={n}")).await,
+            None => self.get_json("/history").await,
+        }
+    }
+
+    /// 单个prompt的执行记录, 未执行完时为None
+    pub async fn history_of(&self, prompt_id: &str) -> AppResult<Option<HistoryItem>> {
+        let mut history: History = self.get_json(&format!("/history/{prompt_id}")).await?;
+        Ok(history.remove(prompt_id))
+    }
+
+    pub async fn queue(&self) -> AppResult<QueueInfo> {
+        self.get_json("/queue").await
+    }
+
+    /// 删除队列中等待的prompt
+    pub async fn delete_queue(&self, prompt_ids: &[String]) -> AppResult<()> {
+        self.post_json("/queue", &json!({ "delete": prompt_ids }))
             .await
-            .expect("Failed to send prompt");
+    }
+
+    pub async fn object_info(&self) -> AppResult<ObjectInfo> {
+        self.get_json("/object_info").await
+    }
+
+    /// 单个comfy类的结点定义
+    pub async fn object_info_of(&self, class_type: &str) -> AppResult<Option<NodeInfo>> {
+        let path = format!("/object_info/{}", urlencode(class_type));
+        let mut info: ObjectInfo = self.get_json(&path).await?;
+        Ok(info.remove(class_type))
+    }
+
+    pub async fn system_stats(&self) -> AppResult<SystemStats> {
+        self.get_json("/system_stats").await
+    }
+
+    /// 中断正在执行的`prompt_id`, 正在跑的已换成别的时服务器不中断
+    pub async fn interrupt(&self, prompt_id: &str) -> AppResult<()> {
+        self.post_json("/interrupt", &json!({"prompt_id": prompt_id}))
+            .await
+    }
+
+    /// 下载图片内容
+    pub async fn view(&self, image: &ImageRef) -> AppResult<Vec<u8>> {
+        let url = self.url("/view");
+        trace!("GET {url} {image:?}");
+        let rsp = self
+            .client
+            .get(url)
+            .query(&[
+                ("filename", image.filename.as_str()),
+                ("subfolder", image.subfolder.as_str()),
+                ("type", image.typ.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(rsp.bytes().await?.to_vec())
+    }
+
+    /// 上传图片到comfy的input目录, 同名覆盖
+    pub async fn upload_image(&self, name: &str, data: Vec<u8>) -> AppResult<UploadedImage> {
+        let url = self.url("/upload/image");
+        trace!("POST {url} {name}");
+        let part = multipart::Part::bytes(data).file_name(name.to_owned());
+        let form = multipart::Form::new()
+            .part("image", part)
+            .text("type", "input")
+            .text("overwrite", "true");
+        let rsp = self
+            .client
+            .post(url)
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(rsp.json().await?)
     }
 }
 
+/// comfy类名里有空格,`|`等, 路径参数需要编码
+fn urlencode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{b:02X}")),
+        }
+    }
+    encoded
+}
+
 #[cfg(test)]
 mod comfy_tests {
     use serde_json::json;
 
+    use super::*;
+
     #[test]
     fn extra_pnginfo_should_work() {
         let data = json!({
@@ -76,4 +580,190 @@ mod comfy_tests {
         println!("{json}");
         assert_eq!(json, r#"{"extra_pnginfo":{"workflow":{"nodes":[]}}}"#);
     }
+
+    #[test]
+    fn history_parsing_should_work() {
+        let data = json!({
+            "a1b2": {
+                "prompt": [3, "a1b2", {}, {"client_id": "c"}, ["207"]],
+                "outputs": {
+                    "207": {
+                        "images": [{"filename": "a_00001_.png", "subfolder": "sketch", "type": "output"}]
+                    },
+                    "245": {"tags": ["1girl"]}
+                },
+                "status": {"status_str": "success", "completed": true, "messages": []}
+            }
+        });
+        let history: History = serde_json::from_value(data).unwrap();
+        let item = history.get("a1b2").unwrap();
+        let images = &item.outputs.get("207").unwrap().images;
+        assert_eq!(images[0].filename, "a_00001_.png");
+        assert_eq!(images[0].typ, "output");
+        assert!(item.outputs.get("245").unwrap().images.is_empty());
+        assert!(item.status.as_ref().unwrap().completed);
+    }
+
+    #[test]
+    fn queue_parsing_should_work() {
+        let data = json!({
+            "queue_running": [[5, "p5", {}, {"client_id": "c"}, ["207"]]],
+            "queue_pending": []
+        });
+        let queue: QueueInfo = serde_json::from_value(data).unwrap();
+        assert_eq!(queue.queue_running[0].number, 5);
+        assert_eq!(queue.queue_running[0].prompt_id, "p5");
+        assert_eq!(queue.queue_running[0].extra_data["client_id"], "c");
+        assert_eq!(queue.queue_running[0].client_id(), Some("c"));
+        assert!(queue.queue_pending.is_empty());
+    }
+
+    #[test]
+    fn object_info_parsing_should_work() {
+        let data = json!({
+            "KSampler": {
+                "input": {
+                    "required": {
+                        "seed": ["INT", {"default": 0}],
+                        "sampler_name": [["euler", "lcm"]]
+                    }
+                },
+                "output": ["LATENT"],
+                "output_is_list": [false],
+                "output_name": ["LATENT"],
+                "name": "KSampler",
+                "display_name": "KSampler",
+                "category": "sampling",
+                "output_node": false
+            }
+        });
+        let info: ObjectInfo = serde_json::from_value(data).unwrap();
+        let ks = info.get("KSampler").unwrap();
+        assert_eq!(ks.input.required["sampler_name"][0][1], "lcm");
+        assert!(ks.input.optional.is_empty());
+    }
+
+    #[test]
+    fn prompt_rsp_parsing_should_work() {
+        let data = json!({"prompt_id": "p1", "number": 7, "node_errors": {}});
+        let queued: QueuedPrompt = serde_json::from_value(data).unwrap();
+        assert_eq!(queued.prompt_id, "p1");
+        assert_eq!(queued.number, 7);
+
+        let data = json!({
+            "error": {
+                "type": "prompt_outputs_failed_validation",
+                "message": "Prompt outputs failed validation",
+                "details": "",
+                "extra_info": {}
+            },
+            "node_errors": {
+                "3": {
+                    "errors": [{
+                        "type": "value_not_in_list",
+                        "message": "Value not in list",
+                        "details": "sampler_name: 'lcmx' not in ['euler', 'lcm']",
+                        "extra_info": {"input_name": "sampler_name"}
+                    }],
+                    "dependent_outputs": ["207"],
+                    "class_type": "KSampler"
+                }
+            }
+        });
+        let rejected: PromptRejected = serde_json::from_value(data).unwrap();
+        assert_eq!(
+            rejected.node_errors["3"].errors[0].extra_info["input_name"],
+            "sampler_name"
+        );
+        let err = AppError::Rejected(Box::new(rejected)).to_string();
+        assert!(err.contains("3(KSampler): Value not in list: sampler_name"));
+    }
+
+    #[test]
+    fn ws_event_parsing_should_work() {
+        let parse = |v: Value| serde_json::from_value::<WsEvent>(v).unwrap();
+        let ev = parse(
+            json!({"type": "status", "data": {"status": {"exec_info": {"queue_remaining": 2}}, "sid": "c"}}),
+        );
+        assert!(matches!(ev, WsEvent::Status(ref m) if m.status.exec_info.queue_remaining == 2));
+        let ev = parse(json!({"type": "executing", "data": {"node": null, "prompt_id": "p"}}));
+        assert!(matches!(ev, WsEvent::Executing(ref m) if m.node.is_none()));
+        assert_eq!(ev.prompt_id(), Some("p"));
+        let ev = parse(
+            json!({"type": "progress", "data": {"value": 3, "max": 8, "prompt_id": "p", "node": "3"}}),
+        );
+        assert!(matches!(ev, WsEvent::Progress(ref m) if m.value == 3 && m.max == 8));
+        let ev = parse(json!({
+            "type": "executed",
+            "data": {
+                "node": "207",
+                "output": {"images": [{"filename": "a.png", "subfolder": "", "type": "output"}]},
+                "prompt_id": "p"
+            }
+        }));
+        assert!(matches!(ev, WsEvent::Executed(ref m) if m.output.images.len() == 1));
+        let ev = parse(json!({
+            "type": "execution_error",
+            "data": {
+                "prompt_id": "p", "node_id": "3", "node_type": "KSampler", "executed": [],
+                "exception_message": "oom", "exception_type": "RuntimeError", "traceback": []
+            }
+        }));
+        assert!(matches!(ev, WsEvent::ExecutionError(ref m) if m.exception_message == "oom"));
+        let ev = parse(json!({"type": "crystools.monitor", "data": {"cpu_utilization": 1}}));
+        assert!(matches!(ev, WsEvent::Unknown(ref m) if m.typ == "crystools.monitor"));
+        assert!(
+            serde_json::from_value::<WsEvent>(json!({"type": "progress", "data": {}})).is_err()
+        );
+    }
+
+    #[test]
+    fn urlencode_should_work() {
+        assert_eq!(urlencode("Image Save"), "Image%20Save");
+        assert_eq!(urlencode("WD14Tagger|pysssss"), "WD14Tagger%7Cpysssss");
+    }
+
+    #[tokio::test]
+    async fn comfy_with_mock_should_work() {
+        let mock = crate::MockComfy::start().await.unwrap();
+        mock.set_object_info(json!({"Image Save": {
+            "input": {"required": {}}, "output": [], "output_is_list": [], "output_name": [],
+            "name": "Image Save", "display_name": "Image Save", "description": "",
+            "category": "WAS Suite/IO", "output_node": true
+        }}));
+        mock.pause(true);
+        let api = Comfy::new(&mock.host(), "test");
+
+        let stats = api.system_stats().await.unwrap();
+        assert_eq!(stats.devices.len(), 1);
+        let info = api.object_info_of("Image Save").await.unwrap();
+        assert!(info.unwrap().output_node);
+
+        let prompt = json!({"1": {"class_type": "Image Save", "inputs": {"filename_prefix": "a"}}});
+        let queued = api.queue_prompt(&prompt).await.unwrap();
+        let queue = api.queue().await.unwrap();
+        assert_eq!(queue.queue_running[0].prompt_id, queued.prompt_id);
+        assert_eq!(mock.received()[0].client_id, "test");
+
+        mock.reject_next();
+        let rejected = api.queue_prompt(&prompt).await;
+        assert!(matches!(rejected, Err(AppError::Rejected(_))));
+
+        let uploaded = api.upload_image("a.png", vec![1, 2, 3]).await.unwrap();
+        assert_eq!(uploaded.name, "a.png");
+        assert_eq!(mock.uploads(), vec!["a.png".to_owned()]);
+        let image = ImageRef {
+            filename: "a_00000_.png".into(),
+            subfolder: "".into(),
+            typ: "output".into(),
+        };
+        assert_eq!(api.view(&image).await.unwrap(), b"a_00000_.png");
+
+        //只中断指定的prompt
+        api.interrupt("other").await.unwrap();
+        assert!(!mock.interrupted());
+        assert_eq!(mock.running(), Some(queued.prompt_id.clone()));
+        api.interrupt(&queued.prompt_id).await.unwrap();
+        assert!(mock.interrupted());
+    }
 }
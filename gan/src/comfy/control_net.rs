@@ -1,3 +1,4 @@
+use macros::ComfyNode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tracing::trace;
@@ -20,7 +21,8 @@ pub enum IdxControlNet {
 }
 
 /// ControlNet stack
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "CR Multi-ControlNet Stack")]
 pub struct CtrlnetStack {
     /// The switch 1
     pub switch_1: String,
@@ -60,7 +62,7 @@ pub struct CtrlnetStack {
     pub image_3: Option<Value>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CnCfg {
     pub model: String,
     pub preprocessor: String,
@@ -154,6 +156,9 @@ impl Default for CtrlnetStack {
 pub struct Ctrlnet {
     pub model: Vec<String>,
     pub preprocessor: Vec<String>,
+    /// SDXL模型, 只和SDXL的ckpt一起用
+    #[serde(default)]
+    pub sdxl: bool,
 }
 
 #[cfg(test)]
@@ -0,0 +1,285 @@
+use std::{collections::HashMap, fmt};
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    dup_titles, is_ui_json, parse_link, parse_nodes, AppResult, Node, ObjectInfo, UiWorkflow,
+    Workflow,
+};
+
+/// 一个输入的变化, 没有该输入时为`None`
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputDiff {
+    pub input: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// 同标题结点的变化
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeDiff {
+    pub title: String,
+    /// class_type变了时为(old, new)
+    pub class_type: Option<(String, String)>,
+    /// 连接变化, 值为`[来源标题, slot]`
+    pub links: Vec<InputDiff>,
+    /// 参数变化
+    pub values: Vec<InputDiff>,
+}
+
+/// 两个流程按结点标题比较, 结点id变了不算变化
+#[derive(Debug, Default)]
+pub struct WorkflowDiff {
+    /// 新增结点的标题
+    pub added: Vec<String>,
+    /// 删除结点的标题
+    pub removed: Vec<String>,
+    pub changed: Vec<NodeDiff>,
+    /// 新流程里重复的标题及结点id, 这些结点不参与比较
+    pub duplicated: Vec<(String, Vec<String>)>,
+}
+
+impl WorkflowDiff {
+    pub fn new<'a>(
+        old: impl IntoIterator<Item = &'a Node>,
+        new: impl IntoIterator<Item = &'a Node>,
+    ) -> Self {
+        let (old, new): (Vec<&Node>, Vec<&Node>) =
+            (old.into_iter().collect(), new.into_iter().collect());
+        let duplicated = dup_titles(new.iter().copied());
+        let is_dup = |n: &&Node| duplicated.iter().any(|(t, _)| t == &n.meta.title);
+        let old_titles = titles(&old);
+        let new_titles = titles(&new);
+        let by_title = |nodes: &[&'a Node]| -> HashMap<String, &'a Node> {
+            nodes.iter().map(|n| (n.meta.title.clone(), *n)).collect()
+        };
+        let (old_map, new_map) = (by_title(&old), by_title(&new));
+
+        let mut diff = Self {
+            duplicated: duplicated.clone(),
+            ..Default::default()
+        };
+        for node in sorted(&new).into_iter().filter(|n| !is_dup(n)) {
+            let title = &node.meta.title;
+            match old_map.get(title) {
+                None => diff.added.push(title.clone()),
+                Some(old_node) => {
+                    let nd = node_diff(old_node, &old_titles, node, &new_titles);
+                    if nd != NodeDiff::default() {
+                        diff.changed.push(nd);
+                    }
+                }
+            }
+        }
+        for node in sorted(&old) {
+            if !new_map.contains_key(&node.meta.title) {
+                diff.removed.push(node.meta.title.clone());
+            }
+        }
+        diff
+    }
+
+    /// api格式或界面格式的流程文件内容, 界面格式用`info`转换
+    pub fn from_json(old: &str, new: &str, info: &ObjectInfo) -> AppResult<Self> {
+        let (old, new) = (json_nodes(old, info)?, json_nodes(new, info)?);
+        Ok(Self::new(old.iter(), new.iter()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.duplicated.is_empty()
+    }
+}
+
+impl Workflow {
+    /// 按标题和`new`比较
+    pub fn diff(&self, new: &Workflow) -> WorkflowDiff {
+        WorkflowDiff::new(self.nodes(), new.nodes())
+    }
+}
+
+fn json_nodes(json: &str, info: &ObjectInfo) -> AppResult<Vec<Node>> {
+    let nodes = if is_ui_json(json) {
+        serde_json::from_str::<UiWorkflow>(json)?.to_nodes(info)?
+    } else {
+        serde_json::from_str(json)?
+    };
+    parse_nodes(nodes)
+}
+
+fn titles<'a>(nodes: &[&'a Node]) -> HashMap<&'a str, &'a str> {
+    nodes
+        .iter()
+        .map(|n| (n.id.as_str(), n.meta.title.as_str()))
+        .collect()
+}
+
+fn sorted<'a>(nodes: &[&'a Node]) -> Vec<&'a Node> {
+    let mut nodes = nodes.to_vec();
+    nodes.sort_by(|a, b| a.meta.title.cmp(&b.meta.title));
+    nodes
+}
+
+/// 输入按标题展开, 连接`[id, slot]`换成`[标题, slot]`, 找不到来源时用`#id`
+fn inputs_by_title(node: &Node, titles: &HashMap<&str, &str>) -> (Map<String, Value>, Vec<String>) {
+    let Ok(Value::Object(mut inputs)) = serde_json::to_value(&node.inputs) else {
+        return Default::default();
+    };
+    let mut links = Vec::new();
+    for (input, value) in inputs.iter_mut() {
+        let Some((from, slot)) = parse_link(value) else {
+            continue;
+        };
+        let from = match titles.get(from) {
+            Some(title) => title.to_string(),
+            None => format!("#{from}"),
+        };
+        *value = json!([from, slot]);
+        links.push(input.clone());
+    }
+    (inputs, links)
+}
+
+fn node_diff(
+    old: &Node,
+    old_titles: &HashMap<&str, &str>,
+    new: &Node,
+    new_titles: &HashMap<&str, &str>,
+) -> NodeDiff {
+    let mut nd = NodeDiff::default();
+    if old.class_type != new.class_type {
+        nd.class_type = Some((old.class_type.clone(), new.class_type.clone()));
+    }
+    let (old_inputs, old_links) = inputs_by_title(old, old_titles);
+    let (new_inputs, new_links) = inputs_by_title(new, new_titles);
+    let inputs = old_inputs
+        .keys()
+        .chain(new_inputs.keys().filter(|k| !old_inputs.contains_key(*k)));
+    for input in inputs {
+        let (old_value, new_value) = (old_inputs.get(input), new_inputs.get(input));
+        if old_value == new_value {
+            continue;
+        }
+        let change = InputDiff {
+            input: input.clone(),
+            old: old_value.cloned(),
+            new: new_value.cloned(),
+        };
+        if old_links.contains(input) || new_links.contains(input) {
+            nd.links.push(change);
+        } else {
+            nd.values.push(change);
+        }
+    }
+    if nd != NodeDiff::default() {
+        nd.title = new.meta.title.clone();
+    }
+    nd
+}
+
+fn fmt_value(value: &Option<Value>) -> String {
+    match value {
+        None => "-".to_owned(),
+        Some(value) => match value.as_array().map(Vec::as_slice) {
+            Some([Value::String(from), slot]) => format!("{from}[{slot}]"),
+            _ => value.to_string(),
+        },
+    }
+}
+
+impl fmt::Display for WorkflowDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (title, ids) in self.duplicated.iter() {
+            writeln!(f, "! {title}: dup title in nodes {}", ids.join(", "))?;
+        }
+        for title in self.added.iter() {
+            writeln!(f, "+ {title}")?;
+        }
+        for title in self.removed.iter() {
+            writeln!(f, "- {title}")?;
+        }
+        for nd in self.changed.iter() {
+            writeln!(f, "~ {}", nd.title)?;
+            if let Some((old, new)) = &nd.class_type {
+                writeln!(f, "    class_type: {old} -> {new}")?;
+            }
+            for change in nd.links.iter().chain(nd.values.iter()) {
+                let (old, new) = (fmt_value(&change.old), fmt_value(&change.new));
+                writeln!(f, "    {}: {old} -> {new}", change.input)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use fixtures::txt2img;
+
+    use super::*;
+
+    /// 结点id都加1000, 连接跟着改
+    fn renumber(json: &str) -> Value {
+        let nodes: Map<String, Value> = serde_json::from_str(json).unwrap();
+        let shift = |id: &str| (id.parse::<u32>().unwrap() + 1000).to_string();
+        let mut renumbered = Map::new();
+        for (id, mut node) in nodes.into_iter() {
+            for value in node["inputs"].as_object_mut().unwrap().values_mut() {
+                if let Some((from, slot)) = parse_link(value) {
+                    *value = json!([shift(from), slot]);
+                }
+            }
+            renumbered.insert(shift(&id), node);
+        }
+        Value::Object(renumbered)
+    }
+
+    #[test]
+    fn diff_should_ignore_ids() {
+        let info = ObjectInfo::default();
+        let new = renumber(txt2img()).to_string();
+        let diff = WorkflowDiff::from_json(txt2img(), &new, &info).unwrap();
+        assert!(diff.is_empty(), "{diff}");
+    }
+
+    #[test]
+    fn diff_should_work() {
+        let mut new = renumber(txt2img());
+        let nodes = new.as_object_mut().unwrap();
+        //改参数, 改连接, 删结点, 加结点, 标题重复
+        nodes["1003"]["inputs"]["steps"] = json!(30);
+        nodes["1003"]["inputs"]["latent_image"] = json!(["1015", 0]);
+        nodes.remove("1254");
+        let mut preview = nodes["1207"].clone();
+        preview["_meta"]["title"] = json!("Preview");
+        nodes.insert("2000".into(), preview);
+        nodes["1035"]["_meta"]["title"] = json!("LoadImage");
+
+        let info = ObjectInfo::default();
+        let diff = WorkflowDiff::from_json(txt2img(), &new.to_string(), &info).unwrap();
+        assert_eq!(diff.added, vec!["Preview"]);
+        //VaeDecode改名成了LoadImage, 和原LoadImage重复
+        assert_eq!(diff.removed, vec!["EmptyLatent", "VaeDecode"]);
+        let dup = (
+            "LoadImage".to_owned(),
+            vec!["1015".to_owned(), "1035".to_owned()],
+        );
+        assert_eq!(diff.duplicated, vec![dup]);
+        let ks = diff.changed.iter().find(|c| c.title == "KSampler").unwrap();
+        assert_eq!(ks.values.len(), 1);
+        assert_eq!(ks.values[0].new, Some(json!(30)));
+        assert_eq!(ks.links[0].old, Some(json!(["EmptyLatent", 0])));
+        let text = diff.to_string();
+        assert!(text.starts_with("! LoadImage: dup title in nodes 1015, 1035\n+ Preview\n"));
+        assert!(
+            text.contains("    latent_image: EmptyLatent[0] -> LoadImage[0]\n"),
+            "{text}"
+        );
+
+        //Workflow::from_json不接受重复标题
+        let err = Workflow::from_json(&new.to_string()).unwrap_err();
+        assert_eq!(err.to_string(), "dup title: LoadImage(1015, 1035)");
+    }
+}
@@ -1,8 +1,10 @@
+use macros::ComfyNode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 /// Efficient loader
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "Efficient Loader")]
 pub struct EfficientLoader {
     /// The name of the checkpoint file
     pub ckpt_name: String,
@@ -35,3 +37,27 @@ pub struct EfficientLoader {
     /// The batch size for loading data
     pub batch_size: u8,
 }
+
+/// SDXL efficient loader, 精炼模型为"None"时不精炼
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "Eff. Loader SDXL")]
+pub struct EfficientLoaderSdxl {
+    pub base_ckpt_name: String,
+    pub base_clip_skip: i8,
+    pub refiner_ckpt_name: String,
+    pub refiner_clip_skip: i8,
+    /// 精炼模型的美学评分条件
+    pub positive_ascore: f32,
+    pub negative_ascore: f32,
+    pub vae_name: String,
+    /// 同`EfficientLoader.positive`, 同时用于基础和精炼模型
+    pub positive: Value,
+    pub negative: String,
+    pub token_normalization: String,
+    pub weight_interpretation: String,
+    pub empty_latent_width: u16,
+    pub empty_latent_height: u16,
+    pub batch_size: u8,
+    pub lora_stack: Option<Value>,
+    pub cnet_stack: Option<Value>,
+}
@@ -0,0 +1,141 @@
+use std::{fmt::Write, str::FromStr};
+
+use crate::{Node, Workflow};
+
+/// 流程图导出格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    /// Graphviz
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            _ => Err(format!("unknown graph format {s}, dot|mermaid")),
+        }
+    }
+}
+
+/// 高亮结点的填充色
+const TOUCHED_COLOR: &str = "#ffd966";
+
+impl Workflow {
+    /// 结点标`标题/class_type`, 连线标`[slot] 输入名`, `touched`里的标题高亮
+    pub fn to_graph(&self, format: GraphFormat, touched: &[&str]) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(touched),
+            GraphFormat::Mermaid => self.to_mermaid(touched),
+        }
+    }
+
+    pub fn to_dot(&self, touched: &[&str]) -> String {
+        let esc = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut dot = String::from("digraph workflow {\n    rankdir=LR;\n    node [shape=box];\n");
+        for node in self.sorted_nodes() {
+            let label = format!("{}\\n{}", esc(&node.meta.title), esc(&node.class_type));
+            let style = match touched.contains(&node.meta.title.as_str()) {
+                true => format!(", style=filled, fillcolor=\"{TOUCHED_COLOR}\""),
+                false => String::new(),
+            };
+            let _ = writeln!(dot, "    \"{}\" [label=\"{label}\"{style}];", esc(&node.id));
+        }
+        for link in self.links() {
+            let _ = writeln!(
+                dot,
+                "    \"{}\" -> \"{}\" [label=\"[{}] {}\"];",
+                esc(&link.from),
+                esc(&link.to),
+                link.slot,
+                esc(&link.input)
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_mermaid(&self, touched: &[&str]) -> String {
+        let esc = |s: &str| s.replace('"', "#quot;");
+        let mut mmd = String::from("flowchart LR\n");
+        let mut highlighted = Vec::new();
+        for node in self.sorted_nodes() {
+            let id = mermaid_id(&node.id);
+            let (title, class_type) = (esc(&node.meta.title), esc(&node.class_type));
+            let _ = writeln!(mmd, "    {id}[\"{title}<br/>{class_type}\"]");
+            if touched.contains(&node.meta.title.as_str()) {
+                highlighted.push(id);
+            }
+        }
+        for link in self.links() {
+            let (from, to) = (mermaid_id(&link.from), mermaid_id(&link.to));
+            let _ = writeln!(
+                mmd,
+                "    {from} -->|\"[{}] {}\"| {to}",
+                link.slot,
+                esc(&link.input)
+            );
+        }
+        if !highlighted.is_empty() {
+            let _ = writeln!(mmd, "    classDef touched fill:{TOUCHED_COLOR}");
+            let _ = writeln!(mmd, "    class {} touched", highlighted.join(","));
+        }
+        mmd
+    }
+
+    fn sorted_nodes(&self) -> impl Iterator<Item = &Node> {
+        self.node_ids()
+            .into_iter()
+            .filter_map(|id| self.by_id(id).ok())
+    }
+}
+
+/// mermaid的结点id只用字母数字下划线
+fn mermaid_id(id: &str) -> String {
+    let id: String = id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("n{id}")
+}
+
+#[cfg(test)]
+mod export_tests {
+    use fixtures::{test_auto_cfg, txt2img};
+
+    use crate::AutoCfg;
+
+    use super::*;
+
+    #[test]
+    fn export_should_work() {
+        let wf = Workflow::from_json(txt2img()).unwrap();
+        let ac = AutoCfg::from_toml(test_auto_cfg()).unwrap();
+        let touched = ac.titles();
+        assert!(touched.contains(&"KSampler"));
+
+        let dot = wf.to_graph(GraphFormat::Dot, &touched);
+        assert!(dot.starts_with("digraph workflow {\n"));
+        assert!(dot.contains(
+            "    \"3\" [label=\"KSampler\\nKSampler\", style=filled, fillcolor=\"#ffd966\"];\n"
+        ));
+        assert!(dot.contains("    \"35\" [label=\"VaeDecode\\nVAEDecode\"];\n"));
+        assert!(dot.contains("    \"3\" -> \"35\" [label=\"[0] samples\"];\n"));
+
+        let mmd = wf.to_graph(GraphFormat::Mermaid, &touched);
+        assert!(mmd.starts_with("flowchart LR\n"));
+        assert!(mmd.contains("    n35[\"VaeDecode<br/>VAEDecode\"]\n"));
+        assert!(mmd.contains("    n3 -->|\"[0] samples\"| n35\n"));
+        let class = mmd.lines().find(|l| l.starts_with("    class ")).unwrap();
+        assert!(class.split([' ', ',']).any(|id| id == "n3"));
+        assert!(!class.split([' ', ',']).any(|id| id == "n35"));
+
+        assert_eq!("mermaid".parse(), Ok(GraphFormat::Mermaid));
+        assert!("png".parse::<GraphFormat>().is_err());
+        assert_eq!(mermaid_id("12:3"), "n12_3");
+    }
+}
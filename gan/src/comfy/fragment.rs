@@ -0,0 +1,251 @@
+use std::{collections::HashMap, fs};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{create_input_id, dup_titles, id_key, parse_link, parse_nodes, AppResult, Workflow};
+
+/// 可复用的子图, api格式的部分结点加上命名的输入/输出端口
+///
+/// ```json
+/// {
+///   "inputs": {"image": [["1", "image"]]},
+///   "outputs": {"image": ["2", 0]},
+///   "nodes": {"1": {...}, "2": {...}}
+/// }
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct Fragment {
+    /// 输入端口 => 片段内接收的[结点id, 输入名]
+    #[serde(default)]
+    pub inputs: HashMap<String, Vec<(String, String)>>,
+    /// 输出端口 => 片段内的[结点id, slot]
+    #[serde(default)]
+    pub outputs: HashMap<String, (String, usize)>,
+    /// api格式的结点, id只在片段内有效
+    pub nodes: HashMap<String, Value>,
+}
+
+/// 片段接入配置, 接入点写`标题`或`标题:slot`, 指流程里某个结点的输出
+#[derive(Debug, Clone, Deserialize)]
+pub struct FragmentCfg {
+    /// 片段文件
+    pub file: String,
+    /// 输入端口 => 接入点, 片段的输入接到这个输出
+    #[serde(default)]
+    pub inputs: HashMap<String, String>,
+    /// 输出端口 => 接入点, 原来用这个输出的都改用片段输出
+    #[serde(default)]
+    pub outputs: HashMap<String, String>,
+}
+
+impl Fragment {
+    pub fn from_json(json: &str) -> AppResult<Self> {
+        let fragment: Self = serde_json::from_str(json)?;
+        let ports = fragment
+            .inputs
+            .values()
+            .flatten()
+            .map(|(id, _)| id)
+            .chain(fragment.outputs.values().map(|(id, _)| id));
+        for id in ports {
+            if !fragment.nodes.contains_key(id) {
+                return Err(format!("fragment port: no node {id}").into());
+            }
+        }
+        Ok(fragment)
+    }
+
+    pub fn from_file(file: &str) -> AppResult<Self> {
+        Self::from_json(fs::read_to_string(file)?.as_str())
+            .map_err(|e| format!("{file}: {e}").into())
+    }
+}
+
+impl Workflow {
+    /// 接入片段, 端口按`inputs`/`outputs`对应到接入点, 返回片段内id => 新id
+    pub fn attach(
+        &mut self,
+        fragment: &Fragment,
+        inputs: &HashMap<String, String>,
+        outputs: &HashMap<String, String>,
+    ) -> AppResult<HashMap<String, String>> {
+        //接入点先解析, 不会找到片段自己的结点
+        let mut in_points = Vec::new();
+        for (port, targets) in fragment.inputs.iter() {
+            let point = inputs
+                .get(port)
+                .ok_or_else(|| format!("input port {port} not attached"))?;
+            in_points.push((self.point(point)?, targets));
+        }
+        let mut out_points = Vec::new();
+        for (port, point) in outputs.iter() {
+            let output = fragment
+                .outputs
+                .get(port)
+                .ok_or_else(|| format!("no output port {port}"))?;
+            out_points.push((self.point(point)?, output));
+        }
+
+        let mut local_ids: Vec<&String> = fragment.nodes.keys().collect();
+        local_ids.sort_by(|a, b| id_key(a).cmp(&id_key(b)));
+        //next_id总是数字
+        let first: u64 = self.next_id().parse().unwrap_or(1);
+        let ids: HashMap<String, String> = local_ids
+            .into_iter()
+            .zip(first..)
+            .map(|(local, id)| (local.clone(), id.to_string()))
+            .collect();
+
+        let mut nodes = HashMap::new();
+        for (local, node) in fragment.nodes.iter() {
+            let mut node = node.clone();
+            if let Some(node_inputs) = node.get_mut("inputs").and_then(Value::as_object_mut) {
+                for (input, value) in node_inputs.iter_mut() {
+                    let Some((from, slot)) = parse_link(value) else {
+                        continue;
+                    };
+                    let from = ids
+                        .get(from)
+                        .ok_or_else(|| format!("fragment {local}.{input}: no node {from}"))?;
+                    *value = create_input_id(from, slot);
+                }
+            }
+            nodes.insert(ids[local].clone(), node);
+        }
+        let mut nodes = parse_nodes(nodes)?;
+        let dups: Vec<String> = dup_titles(&nodes).into_iter().map(|(t, _)| t).collect();
+        if !dups.is_empty() {
+            return Err(format!("fragment dup title: {}", dups.join(", ")).into());
+        }
+        if let Some(node) = nodes.iter().find(|n| self.get_node(&n.meta.title).is_ok()) {
+            return Err(format!("title {} already in workflow", node.meta.title).into());
+        }
+        //片段输入先接在解析好的结点上, 输入名不对时流程还没改
+        for ((id, slot), targets) in in_points {
+            for (local, input) in targets.iter() {
+                let node = nodes
+                    .iter_mut()
+                    .find(|n| n.id == ids[local])
+                    .ok_or_else(|| format!("fragment port: no node {local}"))?;
+                node.set_input(input, create_input_id(&id, slot))?;
+            }
+        }
+        //插入前取输出接入点的使用者, 不会改到片段自己的结点
+        let mut rewires = Vec::new();
+        for ((id, slot), (local, out_slot)) in out_points {
+            let to = create_input_id(&ids[local], *out_slot);
+            for link in self.downstream(&id).into_iter().filter(|l| l.slot == slot) {
+                rewires.push((link, to.clone()));
+            }
+        }
+
+        for node in nodes {
+            self.insert_node(node);
+        }
+        for (link, to) in rewires {
+            self.by_id_mut(&link.to)?.set_input(&link.input, to)?;
+        }
+        Ok(ids)
+    }
+
+    /// 读取`cfg.file`并接入
+    pub fn attach_cfg(&mut self, cfg: &FragmentCfg) -> AppResult<HashMap<String, String>> {
+        let fragment = Fragment::from_file(&cfg.file)?;
+        self.attach(&fragment, &cfg.inputs, &cfg.outputs)
+            .map_err(|e| format!("attach {}: {e}", cfg.file).into())
+    }
+
+    /// `标题`或`标题:slot` => (id, slot), 没写slot时为0
+    fn point(&self, point: &str) -> AppResult<(String, usize)> {
+        let (title, slot) = match point.rsplit_once(':').map(|(t, s)| (t, s.parse())) {
+            Some((title, Ok(slot))) => (title, slot),
+            _ => (point, 0),
+        };
+        let id = self
+            .get_node_id(title)
+            .map_err(|_| format!("attach point {point}: no node {title}"))?;
+        Ok((id.clone(), slot))
+    }
+}
+
+#[cfg(test)]
+mod fragment_tests {
+    use fixtures::{fragment_post, img2img, txt2img};
+
+    use crate::NODE_SAVE_IMAGE;
+
+    use super::*;
+
+    fn points(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(port, point)| (port.to_string(), point.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn attach_should_work() {
+        let fragment = Fragment::from_json(fragment_post()).unwrap();
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let vae = wf.get_node_id("VaeDecode").unwrap().clone();
+        let next = wf.next_id();
+        let inputs = points(&[("image", "VaeDecode")]);
+        let outputs = points(&[("image", "VaeDecode:0")]);
+        let ids = wf.attach(&fragment, &inputs, &outputs).unwrap();
+        assert_eq!(ids["1"], next);
+
+        //VaeDecode -> ImageFilterAfter -> UpscaleImage -> ImageSave
+        let filter = wf.get_node_id("ImageFilterAfter").unwrap();
+        let upscale = wf.get_node_id("UpscaleImage").unwrap();
+        let save = wf.get_node_id(NODE_SAVE_IMAGE).unwrap();
+        assert_eq!(wf.upstream(filter)[0].from, vae);
+        assert_eq!(&wf.upstream(upscale)[0].from, filter);
+        assert_eq!(&wf.upstream(save)[0].from, upscale);
+        assert!(wf.to_json().is_ok());
+    }
+
+    #[test]
+    fn attach_should_report_errors() {
+        let fragment = Fragment::from_json(fragment_post()).unwrap();
+        let inputs = points(&[("image", "VaeDecode")]);
+        let none = HashMap::new();
+        //图生图已经有ImageFilterAfter
+        let mut wf = Workflow::from_json(img2img()).unwrap();
+        let err = wf.attach(&fragment, &inputs, &none).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "title ImageFilterAfter already in workflow"
+        );
+
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let count = wf.nodes().count();
+        let err = wf.attach(&fragment, &none, &none).unwrap_err();
+        assert_eq!(err.to_string(), "input port image not attached");
+        let bad = points(&[("image", "NoSuchNode:1")]);
+        let err = wf.attach(&fragment, &bad, &none).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "attach point NoSuchNode:1: no node NoSuchNode"
+        );
+        let err = wf.attach(&fragment, &inputs, &points(&[("mask", "VaeDecode")]));
+        assert_eq!(err.unwrap_err().to_string(), "no output port mask");
+        assert_eq!(wf.nodes().count(), count);
+
+        //端口接到不存在的输入名, 片段内标题重复, 都不改流程
+        let mut json: Value = serde_json::from_str(fragment_post()).unwrap();
+        json["inputs"]["image"][0][1] = "images".into();
+        let typo = Fragment::from_json(&json.to_string()).unwrap();
+        let err = wf.attach(&typo, &inputs, &none).unwrap_err();
+        assert!(err.to_string().contains("has no input images"), "{err}");
+        let mut json: Value = serde_json::from_str(fragment_post()).unwrap();
+        json["nodes"]["2"]["_meta"]["title"] = "ImageFilterAfter".into();
+        let dup = Fragment::from_json(&json.to_string()).unwrap();
+        let err = wf.attach(&dup, &inputs, &none).unwrap_err();
+        assert_eq!(err.to_string(), "fragment dup title: ImageFilterAfter");
+        assert_eq!(wf.nodes().count(), count);
+
+        let err = Fragment::from_json(r#"{"outputs": {"image": ["9", 0]}, "nodes": {}}"#);
+        assert_eq!(err.unwrap_err().to_string(), "fragment port: no node 9");
+    }
+}
@@ -0,0 +1,479 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use serde_json::Value;
+
+use crate::{AppResult, Inputs, Node, Workflow};
+
+/// 结点间的一条连接, `from`结点第`slot`个输出接到`to`结点的`input`输入
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Link {
+    pub from: String,
+    pub slot: usize,
+    pub to: String,
+    pub input: String,
+}
+
+/// 输入值是连接`[id, slot]`时返回`(id, slot)`, 见`create_input_id`
+pub fn parse_link(value: &Value) -> Option<(&str, usize)> {
+    match value.as_array()?.as_slice() {
+        [Value::String(id), Value::Number(slot)] => Some((id.as_str(), slot.as_u64()? as usize)),
+        _ => None,
+    }
+}
+
+/// comfy的id是数字, 按数字大小排序
+pub(crate) fn id_key(id: &str) -> (usize, &str) {
+    (id.len(), id)
+}
+
+impl Node {
+    /// 按输入名改值, 改完按`class_type`重新解析
+    pub fn set_input(&mut self, input: &str, value: Value) -> AppResult<()> {
+        self.set_inputs([(input, value)])
+    }
+
+    /// 按输入名改多个值, 全部改完再重新解析一次, 解析结构没有的输入名报错
+    pub fn set_inputs<'a>(
+        &mut self,
+        values: impl IntoIterator<Item = (&'a str, Value)>,
+    ) -> AppResult<()> {
+        let mut inputs = serde_json::to_value(&self.inputs)?;
+        let mut names = Vec::new();
+        for (input, value) in values {
+            names.push((input, value.is_null()));
+            inputs[input] = value;
+        }
+        let parsed = match Inputs::from_class_type(&self.class_type, inputs) {
+            Some(Ok(inputs)) => inputs,
+            Some(Err(e)) => {
+                let input: Vec<&str> = names.iter().map(|(n, _)| *n).collect();
+                let input = input.join("|");
+                return Err(format!("{}.{input}: {e}", self.meta.title).into());
+            }
+            None => return Err(format!("{}: unknown class_type", self.meta.title).into()),
+        };
+        //serde会丢掉不认识的字段, 改完还在才算改上了, 可选字段设null时不输出
+        let kept = serde_json::to_value(&parsed)?;
+        for (input, null) in names {
+            if !null && kept.get(input).is_none() {
+                return Err(format!(
+                    "{}({}) has no input {input}",
+                    self.meta.title, self.class_type
+                )
+                .into());
+            }
+        }
+        self.inputs = parsed;
+        Ok(())
+    }
+
+    /// 内置认识的保存|预览图片结点, 其它输出类见`Workflow::is_output`
+    pub fn is_output(&self) -> bool {
+        matches!(self.inputs, Inputs::ImageSave(_) | Inputs::SaveImage(_))
+            || self.class_type == "PreviewImage"
+    }
+
+    /// 所有接到本结点输入的连接
+    pub fn links(&self) -> Vec<Link> {
+        let Ok(Value::Object(inputs)) = serde_json::to_value(&self.inputs) else {
+            return Vec::new();
+        };
+        inputs
+            .iter()
+            .filter_map(|(input, value)| {
+                let (from, slot) = parse_link(value)?;
+                Some(Link {
+                    from: from.to_owned(),
+                    slot,
+                    to: self.id.clone(),
+                    input: input.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl Workflow {
+    /// 按id排序的结点id
+    pub fn node_ids(&self) -> Vec<&String> {
+        let mut ids: Vec<&String> = self.nodes().map(|n| &n.id).collect();
+        ids.sort_by_key(|id| id_key(id));
+        ids
+    }
+
+    /// 流程中所有连接, 按目标结点id排序
+    pub fn links(&self) -> Vec<Link> {
+        self.node_ids()
+            .into_iter()
+            .filter_map(|id| self.by_id(id).ok())
+            .flat_map(|n| n.links())
+            .collect()
+    }
+
+    /// 接到`id`结点输入的连接
+    pub fn upstream(&self, id: &str) -> Vec<Link> {
+        self.by_id(id).map(|n| n.links()).unwrap_or_default()
+    }
+
+    /// 用到`id`结点输出的连接
+    pub fn downstream(&self, id: &str) -> Vec<Link> {
+        self.links().into_iter().filter(|l| l.from == id).collect()
+    }
+
+    /// `id`结点直接或间接依赖的结点, 由近到远
+    pub fn ancestors(&self, id: &str) -> Vec<String> {
+        self.walk(id, |wf, id| {
+            wf.upstream(id).into_iter().map(|l| l.from).collect()
+        })
+    }
+
+    /// 直接或间接用到`id`结点输出的结点, 由近到远
+    pub fn descendants(&self, id: &str) -> Vec<String> {
+        let links = self.links();
+        self.walk(id, |_, id| {
+            links
+                .iter()
+                .filter(|l| l.from == id)
+                .map(|l| l.to.clone())
+                .collect()
+        })
+    }
+
+    fn walk(&self, id: &str, next: impl Fn(&Self, &str) -> Vec<String>) -> Vec<String> {
+        let mut seen = HashSet::from([id.to_owned()]);
+        let mut queue = VecDeque::from([id.to_owned()]);
+        let mut found = Vec::new();
+        while let Some(cur) = queue.pop_front() {
+            for nxt in next(self, &cur) {
+                if self.by_id(&nxt).is_ok() && seen.insert(nxt.clone()) {
+                    found.push(nxt.clone());
+                    queue.push_back(nxt);
+                }
+            }
+        }
+        found
+    }
+
+    /// 拓扑排序, 依赖的结点在前, 有环时报错
+    pub fn topo_order(&self) -> AppResult<Vec<String>> {
+        let ids = self.node_ids();
+        let mut indegree: HashMap<&str, usize> = ids.iter().map(|id| (id.as_str(), 0)).collect();
+        let mut consumers: HashMap<String, Vec<String>> = HashMap::new();
+        for link in self.links() {
+            //悬空连接不参与排序
+            if !indegree.contains_key(link.from.as_str()) {
+                continue;
+            }
+            *indegree.get_mut(link.to.as_str()).unwrap() += 1;
+            consumers.entry(link.from).or_default().push(link.to);
+        }
+        let mut ready: BTreeSet<(usize, &str)> = indegree
+            .iter()
+            .filter(|(_, d)| **d == 0)
+            .map(|(id, _)| id_key(id))
+            .collect();
+        let mut order = Vec::with_capacity(ids.len());
+        while let Some((_, id)) = ready.pop_first() {
+            order.push(id.to_owned());
+            for to in consumers.get(id).into_iter().flatten() {
+                let (to, d) = indegree.get_key_value(to.as_str()).unwrap();
+                let (to, d) = (*to, *d - 1);
+                indegree.insert(to, d);
+                if d == 0 {
+                    ready.insert(id_key(to));
+                }
+            }
+        }
+        if order.len() < ids.len() {
+            let cycle = self.find_cycle().unwrap_or_default();
+            return Err(format!("cycle: {}", cycle.join(" -> ")).into());
+        }
+        Ok(order)
+    }
+
+    /// 找一个环, 返回环上的结点id, 首尾相同
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            Visiting,
+            Done,
+        }
+
+        fn visit(
+            wf: &Workflow,
+            id: &str,
+            marks: &mut HashMap<String, Mark>,
+            path: &mut Vec<String>,
+        ) -> Option<Vec<String>> {
+            match marks.get(id) {
+                Some(Mark::Done) => return None,
+                Some(Mark::Visiting) => {
+                    let start = path.iter().position(|p| p == id).unwrap();
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(id.to_owned());
+                    return Some(cycle);
+                }
+                None => {}
+            }
+            marks.insert(id.to_owned(), Mark::Visiting);
+            path.push(id.to_owned());
+            for link in wf.upstream(id) {
+                if wf.by_id(&link.from).is_err() {
+                    continue;
+                }
+                if let Some(cycle) = visit(wf, &link.from, marks, path) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            marks.insert(id.to_owned(), Mark::Done);
+            None
+        }
+
+        let mut marks = HashMap::new();
+        for id in self.node_ids() {
+            let mut path = Vec::new();
+            if let Some(mut cycle) = visit(self, id, &mut marks, &mut path) {
+                //沿依赖方向找的, 反过来是数据流向
+                cycle.reverse();
+                return Some(cycle);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod graph_tests {
+    use fixtures::txt2img;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        create_input_id, ImageRembg, ObjectInfo, RemoveOpts, NODE_EFFICIENT_LOADER,
+        NODE_IMAGE_FILTER, NODE_IMAGE_SCALESIDE, NODE_KSAMPLER, NODE_SAVE_IMAGE, NODE_TEXT_CONCAT,
+        NODE_TEXT_STRING, NODE_VAE_DECODE,
+    };
+
+    /// 开打标的接法: ImageFilter -> Tagger -> TextConcat -> EfficientLoader
+    fn tagged_wf() -> Workflow {
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let if_id = wf.get_node_id(NODE_IMAGE_FILTER).unwrap().clone();
+        let tagger = wf.get_node_mut("Tagger").unwrap();
+        tagger.tagger_mut().image = Some(create_input_id(&if_id, 0));
+        let tagger_id = tagger.id.clone();
+        let concat = wf.get_node_mut(NODE_TEXT_CONCAT).unwrap();
+        concat.text_concat_mut().text2 = Some(create_input_id(&tagger_id, 0));
+        let concat_id = concat.id.clone();
+        let efficient = wf.get_node_mut(NODE_EFFICIENT_LOADER).unwrap();
+        efficient.efficient_loader_mut().positive = create_input_id(&concat_id, 0);
+        wf
+    }
+
+    #[test]
+    fn parse_link_should_work() {
+        assert_eq!(parse_link(&json!(["134", 2])), Some(("134", 2)));
+        assert_eq!(parse_link(&json!(["134", "2"])), None);
+        assert_eq!(parse_link(&json!([1, 2])), None);
+        assert_eq!(parse_link(&json!("134")), None);
+    }
+
+    #[test]
+    fn graph_should_work() {
+        let wf = Workflow::from_json(txt2img()).unwrap();
+        let ks = wf.get_node_id(NODE_KSAMPLER).unwrap();
+        let efficient = wf.get_node_id(NODE_EFFICIENT_LOADER).unwrap();
+        let upstream = wf.upstream(ks);
+        assert_eq!(upstream.len(), 4);
+        let model = upstream.iter().find(|l| l.input == "model").unwrap();
+        assert_eq!((model.from.as_str(), model.slot), (efficient.as_str(), 0));
+        let downstream = wf.downstream(efficient);
+        assert!(downstream.iter().any(|l| &l.to == ks && l.slot == 2));
+
+        let save = wf.get_node_id(NODE_SAVE_IMAGE).unwrap();
+        let ancestors = wf.ancestors(save);
+        assert!(ancestors.contains(ks) && ancestors.contains(efficient));
+        let scale = wf.get_node_id(NODE_IMAGE_SCALESIDE).unwrap();
+        assert!(wf.descendants(scale).contains(efficient));
+
+        let order = wf.topo_order().unwrap();
+        assert_eq!(order.len(), wf.nodes().count());
+        let pos = |id: &str| order.iter().position(|o| o == id).unwrap();
+        for link in wf.links() {
+            assert!(pos(&link.from) < pos(&link.to), "{link:?}");
+        }
+        assert!(wf.find_cycle().is_none());
+    }
+
+    #[test]
+    fn set_inputs_should_reject_unknown_input() {
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let ks = wf.get_node_mut(NODE_KSAMPLER).unwrap();
+        ks.set_inputs([("steps", json!(12)), ("cfg", json!(6.5))])
+            .unwrap();
+        assert_eq!(ks.k_sampler().steps, 12);
+        let err = ks.set_inputs([("step", json!(30))]).unwrap_err();
+        assert!(err.to_string().contains("has no input step"));
+        assert_eq!(ks.k_sampler().steps, 12);
+    }
+
+    #[test]
+    fn cycle_should_be_detected() {
+        let node = |title: &str, from: &str| {
+            json!({
+                "inputs": {"samples": [from, 0], "vae": [from, 1]},
+                "class_type": "VAEDecode",
+                "_meta": {"title": title}
+            })
+        };
+        let json = json!({"1": node("a", "3"), "2": node("b", "1"), "3": node("c", "2")});
+        let wf = Workflow::from_json(&json.to_string()).unwrap();
+        assert_eq!(wf.find_cycle().unwrap(), vec!["1", "2", "3", "1"]);
+        let err = wf.topo_order().unwrap_err().to_string();
+        assert_eq!(err, "cycle: 1 -> 2 -> 3 -> 1");
+    }
+
+    #[test]
+    fn rem_node_should_report_dangling() {
+        let mut wf = tagged_wf();
+        let tagger = wf.get_node_id("Tagger").unwrap().clone();
+        let concat = wf.get_node_id(NODE_TEXT_CONCAT).unwrap().clone();
+        let dangling = wf.rem_node("Tagger");
+        assert_eq!(dangling.len(), 1);
+        assert_eq!((&dangling[0].to, &dangling[0].from), (&concat, &tagger));
+        assert_eq!(dangling[0].input, "text2");
+        assert!(wf.get_node("Tagger").is_err());
+        assert_eq!(wf.dangling(), dangling);
+        let err = wf.to_json().unwrap_err().to_string();
+        assert_eq!(err, format!("dangling: {concat}.text2 -> {tagger}"));
+    }
+
+    #[test]
+    fn rem_node_should_rewire() {
+        let mut wf = tagged_wf();
+        let ts = wf.get_node_id(NODE_TEXT_STRING).unwrap().clone();
+        let opts = RemoveOpts {
+            rewire: Some((ts.clone(), 0)),
+            ..Default::default()
+        };
+        let removed = wf.rem_node_with("Tagger", &opts).unwrap();
+        assert_eq!(removed.rewired.len(), 1);
+        assert!(removed.dangling.is_empty());
+        let concat = wf.get_node(NODE_TEXT_CONCAT).unwrap();
+        assert_eq!(concat.text_concat().text2, Some(create_input_id(&ts, 0)));
+        assert!(wf.to_json().is_ok());
+
+        let opts = RemoveOpts {
+            rewire: Some(("999".into(), 0)),
+            ..Default::default()
+        };
+        assert!(wf.rem_node_with(NODE_TEXT_CONCAT, &opts).is_err());
+        assert!(wf.get_node(NODE_TEXT_CONCAT).is_ok());
+    }
+
+    #[test]
+    fn rem_node_should_prune_orphans() {
+        let mut wf = tagged_wf();
+        let ts = wf.get_node_id(NODE_TEXT_STRING).unwrap().clone();
+        let efficient = wf.get_node_mut(NODE_EFFICIENT_LOADER).unwrap();
+        efficient.efficient_loader_mut().positive = create_input_id(&ts, 0);
+        let count = wf.nodes().count();
+        let opts = RemoveOpts {
+            prune: true,
+            ..Default::default()
+        };
+        let removed = wf.rem_node_with("Tagger", &opts).unwrap();
+        //TextConcat只剩悬空输入没人用, ImageFilter还有preprocessor在用
+        assert_eq!(removed.ids.len(), 2);
+        assert!(wf.get_node(NODE_TEXT_CONCAT).is_err());
+        assert!(wf.get_node(NODE_IMAGE_FILTER).is_ok());
+        assert_eq!(wf.nodes().count(), count - 2);
+        assert!(removed.dangling.is_empty());
+        assert!(wf.to_json().is_ok());
+    }
+
+    #[test]
+    fn prune_should_keep_output_nodes() {
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let decode = wf.get_node_id(NODE_VAE_DECODE).unwrap().clone();
+        let save = wf.get_node_id(NODE_SAVE_IMAGE).unwrap().clone();
+        assert!(wf.by_id(&save).unwrap().is_output());
+        let opts = RemoveOpts {
+            prune: true,
+            ..Default::default()
+        };
+        //ImageSave只接了VaeDecode, 上游的采样链都剪掉, ImageSave留着报悬空
+        let removed = wf.rem_node_with(NODE_VAE_DECODE, &opts).unwrap();
+        assert_eq!(removed.ids[0], decode);
+        assert!(wf.get_node(NODE_KSAMPLER).is_err());
+        assert!(wf.get_node(NODE_SAVE_IMAGE).is_ok());
+        assert_eq!(removed.dangling.len(), 1);
+        assert_eq!(
+            (&removed.dangling[0].to, &removed.dangling[0].from),
+            (&save, &decode)
+        );
+    }
+
+    #[test]
+    fn prune_should_only_touch_removed_chain() {
+        let mut json: Value = serde_json::from_str(txt2img()).unwrap();
+        let decode = json
+            .as_object()
+            .unwrap()
+            .iter()
+            .find(|(_, n)| n["_meta"]["title"] == NODE_VAE_DECODE)
+            .map(|(id, _)| id.clone())
+            .unwrap();
+        //自定义的保存视频结点, 和原来就悬空又没人用的结点
+        json["900"] = json!({"class_type": "VHS_VideoCombine", "_meta": {"title": "Video"},
+            "inputs": {"images": [decode, 0], "frame_rate": 8}});
+        json["901"] = json!({"class_type": "Foo", "_meta": {"title": "Stray"},
+            "inputs": {"x": ["999", 0]}});
+        let mut wf = Workflow::from_json(&json.to_string()).unwrap();
+        let info: ObjectInfo = serde_json::from_value(json!({
+            "VHS_VideoCombine": {"input": {}, "name": "VHS_VideoCombine", "output_node": true}
+        }))
+        .unwrap();
+        wf.set_output_classes(&info);
+        let opts = RemoveOpts {
+            prune: true,
+            ..Default::default()
+        };
+        let removed = wf.rem_node_with(NODE_VAE_DECODE, &opts).unwrap();
+        assert!(wf.get_node("Video").is_ok());
+        assert!(wf.get_node("Stray").is_ok());
+        assert!(!removed.ids.contains(&"901".to_owned()));
+        assert_eq!(removed.dangling.len(), 2);
+    }
+
+    #[test]
+    fn splice_should_work() {
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let decode = wf.get_node_id(NODE_VAE_DECODE).unwrap().clone();
+        let save = wf.get_node_id(NODE_SAVE_IMAGE).unwrap().clone();
+        let rembg = ImageRembg {
+            model_name: "u2net".into(),
+            image: None,
+        };
+        let id = wf
+            .add_node(NODE_VAE_DECODE, Inputs::ImageRembg(rembg))
+            .unwrap();
+        assert_eq!(id, "256");
+        assert_eq!(wf.by_id(&id).unwrap().meta.title, "VaeDecode_2");
+        assert_eq!(wf.get_node_id("VaeDecode_2").unwrap(), &id);
+
+        //VaeDecode -> ImageRembg -> ImageSave
+        let links = wf.splice(&id, "image", &decode, 0, 0).unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].to, save);
+        assert_eq!(wf.upstream(&save)[0].from, id);
+        assert_eq!(wf.upstream(&id)[0].from, decode);
+        assert!(wf.topo_order().is_ok() && wf.to_json().is_ok());
+
+        let links = wf.replace_output(&id, 0, &decode, 0).unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(wf.upstream(&save)[0].from, decode);
+        assert!(wf.downstream(&id).is_empty());
+        assert!(wf.add_node("Raw", Inputs::Raw(Default::default())).is_err());
+    }
+}
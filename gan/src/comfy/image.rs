@@ -1,10 +1,11 @@
 use anyhow::Context;
-use macros::FromValue;
+use macros::{ComfyNode, FromValue};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 /// Image preprocessor
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "AIO_Preprocessor")]
 pub struct ImagePreprocessor {
     /// The preprocessor used for image processing
     pub preprocessor: String,
@@ -15,7 +16,8 @@ pub struct ImagePreprocessor {
 }
 
 /// Image saver, SaveImage
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "SaveImage")]
 pub struct SaveImage {
     /// The filename prefix
     pub filename_prefix: String,
@@ -24,7 +26,8 @@ pub struct SaveImage {
 }
 
 /// Image Save
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "Image Save")]
 pub struct ImageSave {
     pub output_path: String,
     pub filename_prefix: String,
@@ -53,7 +56,8 @@ pub struct UpscaleSaveImage {
 }
 
 /// Image loader
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "LoadImage")]
 pub struct LoadImage {
     /// image file name in ComfyUI/input dir
     pub image: String,
@@ -62,7 +66,8 @@ pub struct LoadImage {
 }
 
 /// ImageScaleSide "Image scale to side"
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "Image scale to side")]
 pub struct ImageScaleSide {
     pub side_length: u16,
     /// side Longest|Width|Height
@@ -74,7 +79,8 @@ pub struct ImageScaleSide {
     pub image: Option<Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "CR Upscale Image")]
 pub struct UpscaleImage {
     pub upscale_model: String,
     /// rescale|resize
@@ -90,7 +96,8 @@ pub struct UpscaleImage {
 }
 
 /// CropImage "Crop Image TargetSize (JPS)"
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "Crop Image TargetSize (JPS)")]
 pub struct CropImage {
     pub target_w: u16,
     pub target_h: u16,
@@ -105,7 +112,8 @@ pub struct CropImage {
 }
 
 /// ImageFilter "Image Filter Adjustments"
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "Image Filter Adjustments")]
 pub struct ImageFilter {
     pub brightness: f32,
     pub contrast: f32,
@@ -120,7 +128,8 @@ pub struct ImageFilter {
 }
 
 /// EmptyImage
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "EmptyImage")]
 pub struct EmptyImage {
     pub width: u16,
     pub height: u16,
@@ -129,14 +138,16 @@ pub struct EmptyImage {
 }
 
 /// ImageRembg "Image Remove Background (rembg)"
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "Image Remove Background (rembg)")]
 pub struct ImageRembg {
     pub model_name: String,
     pub image: Option<Value>,
 }
 
 /// Tagger "WD14Tagger|pysssss"
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "WD14Tagger|pysssss")]
 pub struct Tagger {
     /// wd-v1-4-convnextv2-tagger-v2|wd-v1-4-moat-tagger-v2
     pub model: String,
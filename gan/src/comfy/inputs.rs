@@ -1,84 +1,25 @@
-use anyhow::Context;
 use macros::FromNode;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-
-use crate::{
-    CLIPVisionLoader, CannyEdgePreprocessor, CropImage, CtrlnetStack, EfficientLoader, EmptyImage,
-    EmptyLatent, HEDPreprocessor, IPAdapterApply, IPAdapterModelLoader, ImageFilter,
-    ImagePreprocessor, ImageRembg, ImageSave, ImageScaleSide, KSampler, LeReSDepthMapPreprocessor,
-    LineArtPreprocessor, LineartStandardPreprocessor, LoadImage, LoraStack, LoraStacker,
-    MLSDPreprocessor, MiDaSDepthMapPreprocessor, OpenposePreprocessor, PreprocessorSwitchAfter,
-    PreprocessorSwitchPre, RepeatLatent, SaveImage, Tagger, TextConcat, TextString,
-    TilePreprocessor, TxtimgSwitch, UpscaleImage, VaeDecode, VaeEncode,
-};
-
-// TODO: 准确类型可能需要自已实现根据class_type来判断, 直接ComfyUI api json解析丢失类型信息
-/// Node inputs
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(untagged)]
-#[derive(FromNode)]
-pub enum Inputs {
-    /// Load Image
-    LoadImage(LoadImage),
-    /// CR LoRA Stack
-    LoraStack(LoraStack),
-    LoraStacker(LoraStacker),
-    /// CR Multi-ControlNet Stack
-    CtrlnetStack(CtrlnetStack),
-    /// ImagePreprocessor
-    ImagePreprocessor(ImagePreprocessor),
-    /// Efficient Loader
-    EfficientLoader(EfficientLoader),
-    /// KSampler
-    KSampler(KSampler),
-    /// VAE Decode
-    VaeDecode(VaeDecode),
-    VaeEncode(VaeEncode),
-    /// Save Image
-    ImageSave(ImageSave),
-    SaveImage(SaveImage),
-    ImageScaleSide(ImageScaleSide),
-    UpscaleImage(UpscaleImage),
-    CropImage(CropImage),
-    ImageFilter(ImageFilter),
-    EmptyImage(EmptyImage),
-    ImageRembg(ImageRembg),
-
-    EmptyLatent(EmptyLatent),
-    RepeatLatent(RepeatLatent),
-
-    /// Preprocessor
-    CannyEdgePreprocessor(CannyEdgePreprocessor),
-    OpenposePreprocessor(OpenposePreprocessor),
-    LineArtPreprocessor(LineArtPreprocessor),
-    TilePreprocessor(TilePreprocessor),
-    HEDPreprocessor(HEDPreprocessor),
-    LeReSDepthMapPreprocessor(LeReSDepthMapPreprocessor),
-    MiDaSDepthMapPreprocessor(MiDaSDepthMapPreprocessor),
-    LineartStandardPreprocessor(LineartStandardPreprocessor),
-    MLSDPreprocessor(MLSDPreprocessor),
-
-    /// IPAdapter
-    IPAdapterApply(IPAdapterApply),
-    IPAdapterModelLoader(IPAdapterModelLoader),
-    CLIPVisionLoader(CLIPVisionLoader),
-
-    /// Switch
-    TxtimgSwitch(TxtimgSwitch),
-    PreprocessorSwitchPre(PreprocessorSwitchPre),
-    PreprocessorSwitchAfter(PreprocessorSwitchAfter),
-
-    /// Text
-    TextString(TextString),
-    TextConcat(TextConcat),
-    Tagger(Tagger),
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// 变体列表由`build.rs`从`#[derive(ComfyNode)]`的结构生成, 新结点不用改这里
+macro_rules! comfy_inputs {
+    ($($node:ident),* $(,)?) => {
+        /// Node inputs, 按`class_type`解析, 见`Inputs::from_class_type`
+        /// 变体都是`X(X)`, `X`的comfy类由`#[derive(ComfyNode)]`声明
+        #[derive(Debug, Serialize, Clone)]
+        #[serde(untagged)]
+        #[derive(FromNode)]
+        // 开关结点字段多, 结点数量少, 不装箱
+        #[allow(clippy::large_enum_variant)]
+        pub enum Inputs {
+            $($node(crate::$node),)*
+
+            /// 不认识的结点, 原样保留输入, 不做随机
+            #[comfy(other)]
+            Raw(Map<String, Value>),
+        }
+    };
 }
 
-impl TryFrom<Value> for Inputs {
-    type Error = anyhow::Error;
-
-    fn try_from(value: Value) -> Result<Self, Self::Error> {
-        serde_json::from_value(value).context("Inputs")
-    }
-}
+include!(concat!(env!("OUT_DIR"), "/comfy_nodes.rs"));
@@ -1,10 +1,11 @@
 use anyhow::Context;
-use macros::FromValue;
+use macros::{ComfyNode, FromValue};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 /// IPAdapterApply
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "IPAdapterApply")]
 pub struct IPAdapterApply {
     pub weight: f32,
     pub noise: f32,
@@ -22,13 +23,15 @@ pub struct IPAdapterApply {
 }
 
 /// IPAdapterModelLoader
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "IPAdapterModelLoader")]
 pub struct IPAdapterModelLoader {
     pub ipadapter_file: String,
 }
 
 /// CLIPVisionLoader
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "CLIPVisionLoader")]
 pub struct CLIPVisionLoader {
     pub clip_name: String,
 }
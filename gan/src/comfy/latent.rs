@@ -1,8 +1,10 @@
+use macros::ComfyNode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 /// EmptyLatentImage
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "EmptyLatentImage")]
 pub struct EmptyLatent {
     pub width: u16,
     pub height: u16,
@@ -10,7 +12,8 @@ pub struct EmptyLatent {
 }
 
 /// RepeatLatentBatch
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "RepeatLatentBatch")]
 pub struct RepeatLatent {
     pub amount: u8,
     pub samples: Value,
@@ -1,3 +1,4 @@
+use macros::ComfyNode;
 use serde::{Deserialize, Serialize};
 use tracing::trace;
 
@@ -10,7 +11,8 @@ pub enum IdxLoRA {
 }
 
 /// LoRA stack
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "CR LoRA Stack")]
 pub struct LoraStack {
     /// switch 1 On|Off
     pub switch_1: String,
@@ -38,7 +40,7 @@ pub struct LoraStack {
     pub clip_weight_3: f32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct LoraCfg {
     pub lora_name: String,
     pub model_weight: f32,
@@ -112,7 +114,8 @@ impl Default for LoraStack {
 }
 
 /// LoRA stacker
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "LoRA Stacker")]
 pub struct LoraStacker {
     pub input_mode: String,
     pub lora_count: u8,
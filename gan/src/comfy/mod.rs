@@ -23,6 +23,7 @@ pub const NODE_IMAGE_SCALESIDE: &str = "ImageScaleSide";
 pub const NODE_TEXT_STRING: &str = "TextString";
 pub const NODE_TEXT_CONCAT: &str = "TextConcat";
 pub const NODE_IMAGE_TAGGER: &str = "Tagger";
+pub const NODE_VAE_DECODE: &str = "VaeDecode";
 
 mod api;
 pub use api::*;
@@ -30,6 +31,21 @@ pub use api::*;
 mod workflow;
 pub use workflow::*;
 
+mod graph;
+pub use graph::*;
+
+mod ui;
+pub use ui::*;
+
+mod diff;
+pub use diff::*;
+
+mod fragment;
+pub use fragment::*;
+
+mod export;
+pub use export::*;
+
 mod node;
 pub use node::*;
 
@@ -72,10 +88,10 @@ pub use ip_adapter::*;
 use std::collections::HashMap;
 use std::sync::OnceLock;
 
-/// 自己类 => comfy类
+/// 自己类 => comfy类, 由`#[derive(ComfyNode)]`生成
 pub fn my_class_map() -> &'static HashMap<&'static str, &'static str> {
     static HASHMAP: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
-    HASHMAP.get_or_init(|| serde_json::from_str(fixtures::class_names()).unwrap())
+    HASHMAP.get_or_init(|| Inputs::class_names().iter().copied().collect())
 }
 /// comfy类 => 自己类
 pub fn comfy_class_map() -> &'static HashMap<&'static str, &'static str> {
@@ -135,7 +151,7 @@ mod comfy_tests {
         assert_eq!(cn.switch_1, "Off");
         let cn2 = node.ctrlnet_stack();
         assert_eq!(cn.switch_1, cn2.switch_1);
-        let mut cn3 = CtrlnetStack::from(&node);
+        let mut cn3 = CtrlnetStack::try_from(&node).unwrap();
         assert_eq!(cn.switch_1, cn3.switch_1);
         let cfg = CnCfg::default();
         cn3.enable(IdxControlNet::ControlNet1, &cfg);
@@ -150,6 +166,73 @@ mod comfy_tests {
         assert_eq!(node.ctrlnet_stack().switch_1, "Off");
     }
 
+    #[test]
+    fn node_class_type_dispatch_should_work() {
+        let mut node: Node = serde_json::from_value(serde_json::json!({
+            "inputs": {"filename_prefix": "a", "images": ["1", 0]},
+            "class_type": "SaveImage",
+            "_meta": {"title": "Save"}
+        }))
+        .unwrap();
+        assert!(matches!(node.inputs, Inputs::SaveImage(_)));
+        assert_eq!(node.inputs.class_type(), Some("SaveImage"));
+        for class_type in Inputs::class_types() {
+            assert!(comfy_class_map().contains_key(class_type), "{class_type}");
+        }
+        //derive(ComfyNode)生成的类型和转换
+        assert_eq!(<ImageSave as ComfyNode>::CLASS_TYPE, "Image Save");
+        let inputs: Inputs = node.save_image().clone().into();
+        assert_eq!(inputs.class_type(), Some("SaveImage"));
+        assert!(node.try_save_image_mut().is_ok());
+        let err = node.try_k_sampler().unwrap_err().to_string();
+        assert_eq!(err, "Save(SaveImage) not KSampler");
+    }
+
+    #[test]
+    fn node_mismatch_should_report_field() {
+        let err = serde_json::from_value::<Node>(serde_json::json!({
+            "inputs": {"seed": 1, "steps": "x", "cfg": 7.0, "sampler_name": "euler",
+                "scheduler": "normal", "denoise": 1.0},
+            "class_type": "KSampler",
+            "_meta": {"title": "Sampler"}
+        }))
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.starts_with("Sampler(KSampler).steps: invalid type"),
+            "{err}"
+        );
+        let wf = r#"{"7": {"inputs": {}, "class_type": "LoadImage", "_meta": {"title": "L"}}}"#;
+        let err = Workflow::from_json(wf).unwrap_err().to_string();
+        assert_eq!(err, "node 7 L(LoadImage): missing field `image`");
+    }
+
+    #[test]
+    fn unknown_node_should_round_trip() {
+        let json = serde_json::json!({
+            "1": {
+                "inputs": {"image": "a.png", "upload": "image"},
+                "class_type": "LoadImage",
+                "_meta": {"title": "L"}
+            },
+            "2": {
+                "inputs": {
+                    "images": ["1", 0],
+                    "nested": {"list": [1, 2.5, "x", null], "flag": true},
+                    "weight": 0.30000000000000004
+                },
+                "class_type": "SomeCustomNode",
+                "_meta": {"title": "Custom"}
+            }
+        });
+        let wf = Workflow::from_json(&json.to_string()).unwrap();
+        let node = wf.get_node("Custom").unwrap();
+        assert!(matches!(node.inputs, Inputs::Raw(_)));
+        assert_eq!(node.inputs.class_type(), None);
+        let out = wf.to_json().unwrap();
+        assert_eq!(out["2"], json["2"]);
+    }
+
     #[test]
     fn json_parsing_should_work() {
         let cn = CtrlnetStack::default();
@@ -1,15 +1,10 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
-use crate::{
-    CLIPVisionLoader, CannyEdgePreprocessor, CropImage, CtrlnetStack, EfficientLoader, EmptyImage,
-    EmptyLatent, IPAdapterApply, IPAdapterModelLoader, ImageFilter, ImagePreprocessor, ImageRembg,
-    ImageSave, ImageScaleSide, Inputs, KSampler, LineArtPreprocessor, LineartStandardPreprocessor,
-    LoadImage, LoraStack, LoraStacker, RepeatLatent, SaveImage, Tagger, TextConcat, TextString,
-    TilePreprocessor, VaeDecode,
-};
+use crate::Inputs;
 
 /// A node in the comfy ui workflow
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 pub struct Node {
     /// node inputs
     pub inputs: Inputs,
@@ -23,59 +18,58 @@ pub struct Node {
     pub id: String,
 }
 
-use paste::paste;
-macro_rules! impl_input_methods {
-    ($input_type:ident) => {
-        paste! {
-            pub fn [<$input_type:snake>](&self) -> &$input_type {
-                match &self.inputs {
-                    Inputs::$input_type(v) => v,
-                    _ => panic!("{} not {}", self.meta.title, stringify!($input_type)),
-                }
-            }
+/// 按`class_type`选`Inputs`变体, 出错时报结点标题和字段
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawNode {
+            inputs: Value,
+            class_type: String,
+            #[serde(rename = "_meta")]
+            meta: Meta,
+        }
 
-            pub fn [<$input_type:snake _mut>](&mut self) -> &mut $input_type {
-                match &mut self.inputs {
-                    Inputs::$input_type(v) => v,
-                    _ => panic!("{} not {}", self.meta.title, stringify!($input_type)),
-                }
+        let RawNode {
+            inputs,
+            class_type,
+            meta,
+        } = RawNode::deserialize(deserializer)?;
+        let name = format!("{}({class_type})", meta.title);
+        let inputs = match Inputs::from_class_type(&class_type, inputs) {
+            Some(Ok(inputs)) => inputs,
+            Some(Err(e)) => {
+                let path = e.path().to_string();
+                let e = e.into_inner();
+                return Err(match path.as_str() {
+                    "." => de::Error::custom(format!("{name}: {e}")),
+                    _ => de::Error::custom(format!("{name}.{path}: {e}")),
+                });
             }
-        }
-    };
+            None => return Err(de::Error::custom(format!("{name}: unknown class_type"))),
+        };
+        Ok(Self {
+            inputs,
+            class_type,
+            meta,
+            id: String::new(),
+        })
+    }
+}
+
+/// 结点输入, 用`#[derive(ComfyNode)]`和`#[comfy(class_type = "...")]`声明,
+/// 同时生成`Inputs`同名变体的转换和`Node`的类型访问方法, 见`macros`
+pub trait ComfyNode: Into<Inputs> {
+    /// comfy类
+    const CLASS_TYPE: &'static str;
 }
 
 impl Node {
     pub fn inputs_mut(&mut self) -> &mut Inputs {
         &mut self.inputs
     }
-
-    impl_input_methods!(CtrlnetStack);
-    impl_input_methods!(EfficientLoader);
-    impl_input_methods!(ImagePreprocessor);
-    impl_input_methods!(ImageSave);
-    impl_input_methods!(CropImage);
-    impl_input_methods!(SaveImage);
-    impl_input_methods!(RepeatLatent);
-    impl_input_methods!(EmptyLatent);
-    impl_input_methods!(LoadImage);
-    impl_input_methods!(ImageFilter);
-    impl_input_methods!(EmptyImage);
-    impl_input_methods!(ImageRembg);
-    impl_input_methods!(ImageScaleSide);
-    impl_input_methods!(LoraStack);
-    impl_input_methods!(LoraStacker);
-    impl_input_methods!(KSampler);
-    impl_input_methods!(VaeDecode);
-    impl_input_methods!(LineArtPreprocessor);
-    impl_input_methods!(LineartStandardPreprocessor);
-    impl_input_methods!(TilePreprocessor);
-    impl_input_methods!(CannyEdgePreprocessor);
-    impl_input_methods!(IPAdapterApply);
-    impl_input_methods!(IPAdapterModelLoader);
-    impl_input_methods!(CLIPVisionLoader);
-    impl_input_methods!(TextString);
-    impl_input_methods!(TextConcat);
-    impl_input_methods!(Tagger);
 }
 
 /// Node meta
@@ -1,8 +1,10 @@
+use macros::ComfyNode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 /// Canny edge preprocessor
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "CannyEdgePreprocessor")]
 pub struct CannyEdgePreprocessor {
     pub low_threshold: u8,
     pub high_threshold: u8,
@@ -11,7 +13,8 @@ pub struct CannyEdgePreprocessor {
 }
 
 /// Openpose preprocessor
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "OpenposePreprocessor")]
 pub struct OpenposePreprocessor {
     pub detect_hand: String,
     pub detect_body: String,
@@ -21,7 +24,8 @@ pub struct OpenposePreprocessor {
 }
 
 /// Line art preprocessor
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "LineArtPreprocessor")]
 pub struct LineArtPreprocessor {
     pub coarse: String,
     pub resolution: u16,
@@ -29,7 +33,8 @@ pub struct LineArtPreprocessor {
 }
 
 /// Tile preprocessor
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "TilePreprocessor")]
 pub struct TilePreprocessor {
     //pyrUp_iters {"default": 3, "min": 1, "max": 10, "step": 1}
     #[serde(rename = "pyrUp_iters")]
@@ -39,7 +44,8 @@ pub struct TilePreprocessor {
 }
 
 /// HED preprocessor
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "HEDPreprocessor")]
 pub struct HEDPreprocessor {
     pub safe: String,
     pub resolution: u16,
@@ -47,7 +53,8 @@ pub struct HEDPreprocessor {
 }
 
 /// LeReS depth map preprocessor
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "LeReS-DepthMapPreprocessor")]
 pub struct LeReSDepthMapPreprocessor {
     // rm_nearest {"default": 0.0, "min": 0.0, "max": 100, "step": 0.1}
     pub rm_nearest: f32,
@@ -59,7 +66,8 @@ pub struct LeReSDepthMapPreprocessor {
 }
 
 /// MiDaS depth map preprocessor
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "MiDaS-DepthMapPreprocessor")]
 pub struct MiDaSDepthMapPreprocessor {
     // a {"default": np.pi * 2.0, "min": 0.0, "max": np.pi * 5.0, "step": 0.05}
     pub a: f32,
@@ -76,7 +84,8 @@ pub struct ZoeDepthMapPreprocessor {
     pub image: Option<Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "LineartStandardPreprocessor")]
 pub struct LineartStandardPreprocessor {
     // guassian_sigma {"default": 6.0, "min": 0.0, "max": 100.0}
     pub guassian_sigma: f32,
@@ -86,7 +95,8 @@ pub struct LineartStandardPreprocessor {
     pub image: Option<Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "M-LSDPreprocessor")]
 pub struct MLSDPreprocessor {
     // score_threshold {"default": 0.1, "min": 0.01, "max": 2.0, "step": 0.01}
     pub score_threshold: f32,
@@ -1,8 +1,10 @@
+use macros::ComfyNode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 /// K-sampler
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "KSampler")]
 pub struct KSampler {
     pub seed: i64,
     pub sampler_name: String,
@@ -18,3 +20,62 @@ pub struct KSampler {
     /// generate steps [1, 30]
     pub steps: u8,
 }
+
+/// KSampler SDXL (Eff.), 接`EfficientLoaderSdxl`的sdxl_tuple
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "KSampler SDXL (Eff.)")]
+pub struct KSamplerSdxl {
+    pub noise_seed: i64,
+    pub steps: u8,
+    pub cfg: f32,
+    pub sampler_name: String,
+    pub scheduler: String,
+    pub start_at_step: u8,
+    /// 从第几步换精炼模型, -1不精炼
+    pub refine_at_step: i16,
+    pub preview_method: String,
+    pub vae_decode: String,
+    pub sdxl_tuple: Option<Value>,
+    pub latent_image: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optional_vae: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<Value>,
+}
+
+/// KSampler (Advanced), 可以只跑`[start_at_step, end_at_step)`, 用于分步采样
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "KSamplerAdvanced")]
+pub struct KSamplerAdvanced {
+    /// enable|disable
+    pub add_noise: String,
+    pub noise_seed: i64,
+    pub steps: u8,
+    pub cfg: f32,
+    pub sampler_name: String,
+    pub scheduler: String,
+    pub start_at_step: u16,
+    /// 10000到最后
+    pub end_at_step: u16,
+    /// enable|disable, 留噪声给下一个采样器
+    pub return_with_leftover_noise: String,
+    pub model: Option<Value>,
+    pub positive: Option<Value>,
+    pub negative: Option<Value>,
+    pub latent_image: Option<Value>,
+}
+
+/// SamplerCustom, 采样方法和步数由`sampler`(KSamplerSelect)和`sigmas`(BasicScheduler等)结点给
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "SamplerCustom")]
+pub struct SamplerCustom {
+    pub add_noise: bool,
+    pub noise_seed: i64,
+    pub cfg: f32,
+    pub model: Option<Value>,
+    pub positive: Option<Value>,
+    pub negative: Option<Value>,
+    pub sampler: Option<Value>,
+    pub sigmas: Option<Value>,
+    pub latent_image: Option<Value>,
+}
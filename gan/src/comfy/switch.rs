@@ -1,10 +1,11 @@
 use anyhow::Context;
-use macros::FromValue;
+use macros::{ComfyNode, FromValue};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 /// 文生图|图生图开关 "CR Img2Img Process Switch"
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "CR Img2Img Process Switch")]
 pub struct TxtimgSwitch {
     /// txt2img|img2img
     #[serde(rename = "Input")]
@@ -15,28 +16,39 @@ pub struct TxtimgSwitch {
 }
 
 /// PreprocessorSwitchPre 预处理开关 ImpactInversedSwitch
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "ImpactInversedSwitch")]
 pub struct PreprocessorSwitchPre {
     /// select [1,11]
     pub select: u8,
     pub input: Value,
 }
 /// PreprocessorSwitchAfter 预处理开关 ImpactSwitch
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "ImpactSwitch")]
 pub struct PreprocessorSwitchAfter {
     /// select [1,11]
     pub select: u8,
     /// true
     pub sel_mode: bool,
-    //
+    //输入都是可选的, 没接的不提交
     pub input1: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub input2: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub input3: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub input4: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub input5: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub input6: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub input7: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub input8: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub input9: Value,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
     pub input10: Value,
 }
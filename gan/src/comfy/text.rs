@@ -1,10 +1,11 @@
 use anyhow::Context;
-use macros::FromValue;
+use macros::{ComfyNode, FromValue};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 /// Text String
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "Text String")]
 pub struct TextString {
     /// positive prompts
     pub text: String,
@@ -14,7 +15,8 @@ pub struct TextString {
 }
 
 /// Text Concatenate (JPS)
-#[derive(Debug, Serialize, Deserialize, Clone, FromValue)]
+#[derive(Debug, Serialize, Deserialize, Clone, FromValue, ComfyNode)]
+#[comfy(class_type = "Text Concatenate (JPS)")]
 pub struct TextConcat {
     /// delimiter comma|space|none
     pub delimiter: String,
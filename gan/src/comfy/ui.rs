@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::warn;
+
+use crate::{create_input_id, AppResult, NodeInfo, ObjectInfo, Workflow};
+
+/// 只在界面里连线/给值的结点, 不提交
+const UI_ONLY_NODES: [&str; 3] = ["Reroute", "PrimitiveNode", "Note"];
+const NODE_REROUTE: &str = "Reroute";
+const NODE_PRIMITIVE: &str = "PrimitiveNode";
+/// 结点模式, 静音的不执行, 旁路的输入直通到同类型输出
+const MODE_NEVER: u8 = 2;
+const MODE_BYPASS: u8 = 4;
+
+/// comfy界面保存的流程(`nodes`/`links`/`widgets_values`), 只取转换用到的字段
+#[derive(Debug, Deserialize)]
+pub struct UiWorkflow {
+    pub nodes: Vec<UiNode>,
+    #[serde(default)]
+    pub links: Vec<UiLink>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UiNode {
+    pub id: u64,
+    #[serde(rename = "type")]
+    pub class_type: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub mode: u8,
+    #[serde(default)]
+    pub inputs: Vec<UiInput>,
+    #[serde(default)]
+    pub widgets_values: Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UiInput {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub typ: Value,
+    #[serde(default)]
+    pub link: Option<u64>,
+    /// 有值时是转成输入的widget
+    #[serde(default)]
+    pub widget: Option<Value>,
+}
+
+/// [link_id, from, from_slot, to, to_slot, type]
+#[derive(Debug, Deserialize)]
+pub struct UiLink(pub u64, pub u64, pub usize, pub u64, pub usize, pub Value);
+
+/// 顶层有`nodes`数组的是界面格式, api格式顶层是结点id
+pub fn is_ui_json(json: &str) -> bool {
+    serde_json::from_str::<Value>(json).is_ok_and(|v| v.get("nodes").is_some_and(Value::is_array))
+}
+
+/// `widgets_values`按`/object_info`里widget输入的顺序排列
+struct Widget {
+    /// seed后面界面多一个`control_after_generate`值
+    control: bool,
+    /// LoadImage后面多一个`upload`值, api格式里也有
+    upload: bool,
+}
+
+impl Widget {
+    fn from_def(name: &str, def: &Value) -> Option<Self> {
+        let typ = def.get(0)?;
+        let opts = def.get(1);
+        let opt = |key: &str| opts.and_then(|o| o.get(key)).and_then(Value::as_bool) == Some(true);
+        let is_widget = match typ.as_str() {
+            Some("INT" | "FLOAT" | "STRING" | "BOOLEAN" | "COMBO") => !opt("forceInput"),
+            Some(_) => false,
+            None => typ.is_array(),
+        };
+        if !is_widget {
+            return None;
+        }
+        let seed = typ == "INT" && (name == "seed" || name == "noise_seed");
+        Some(Self {
+            control: seed || opt("control_after_generate"),
+            upload: opt("image_upload"),
+        })
+    }
+}
+
+impl UiWorkflow {
+    /// 转成api格式的结点, widget值按`info`对应到输入名, 保留标题
+    pub fn to_nodes(&self, info: &ObjectInfo) -> AppResult<HashMap<String, Value>> {
+        let mut nodes = HashMap::new();
+        for node in self.nodes.iter() {
+            let class_type = node.class_type.as_str();
+            if node.mode == MODE_NEVER || node.mode == MODE_BYPASS {
+                continue;
+            }
+            if UI_ONLY_NODES.contains(&class_type) {
+                continue;
+            }
+            let Some(ni) = info.get(class_type) else {
+                return Err(format!("node {}({class_type}) not in object_info", node.id).into());
+            };
+            let title = match node.title.as_deref() {
+                Some(title) => title,
+                None if ni.display_name.is_empty() => class_type,
+                None => &ni.display_name,
+            };
+            let inputs = self.node_inputs(node, ni, title);
+            let node_json = json!({
+                "inputs": inputs,
+                "class_type": class_type,
+                "_meta": {"title": title},
+            });
+            nodes.insert(node.id.to_string(), node_json);
+        }
+        Ok(nodes)
+    }
+
+    fn node_inputs(&self, node: &UiNode, ni: &NodeInfo, title: &str) -> Map<String, Value> {
+        let class_type = &node.class_type;
+        let values = node.widgets_values.as_array().cloned().unwrap_or_default();
+        let mut values = values.into_iter();
+        let mut inputs = Map::new();
+        let defs = ni.input.required.iter().chain(ni.input.optional.iter());
+        for (name, def) in defs {
+            let Some(widget) = Widget::from_def(name, def) else {
+                continue;
+            };
+            let Some(value) = values.next() else {
+                //结点版本比服务器旧, 少的widget由服务器用默认值
+                warn!("{title}({class_type}): no widget value for {name}");
+                break;
+            };
+            if widget.control {
+                values.next();
+            }
+            let converted = node
+                .inputs
+                .iter()
+                .find(|i| &i.name == name && i.widget.is_some());
+            //新版界面每个widget都列在`inputs`里, 没连线的用widget值, 连线来源静音时不提交
+            match converted.and_then(|i| i.link) {
+                Some(link) => {
+                    if let Some(value) = self.resolve(link, 0) {
+                        inputs.insert(name.clone(), value);
+                    }
+                }
+                None => {
+                    inputs.insert(name.clone(), value);
+                }
+            }
+            if widget.upload {
+                if let Some(upload) = values.next() {
+                    inputs.insert("upload".to_owned(), upload);
+                }
+            }
+        }
+        let unused = values.count();
+        if unused > 0 {
+            warn!("{title}({class_type}): {unused} widget values unused");
+        }
+        for input in node.inputs.iter().filter(|i| i.widget.is_none()) {
+            if let Some(value) = input.link.and_then(|l| self.resolve(l, 0)) {
+                inputs.insert(input.name.clone(), value);
+            }
+        }
+        inputs
+    }
+
+    /// 连线的来源, 穿过Reroute和旁路结点, PrimitiveNode直接给值, 来源静音时为`None`
+    fn resolve(&self, link_id: u64, depth: usize) -> Option<Value> {
+        if depth > self.nodes.len() {
+            return None;
+        }
+        let UiLink(_, from, slot, _, _, typ) = self.links.iter().find(|l| l.0 == link_id)?;
+        let node = self.nodes.iter().find(|n| n.id == *from)?;
+        if node.mode == MODE_NEVER {
+            return None;
+        }
+        if node.class_type == NODE_PRIMITIVE {
+            return node.widgets_values.get(0).cloned();
+        }
+        if node.class_type == NODE_REROUTE {
+            let link = node.inputs.first()?.link?;
+            return self.resolve(link, depth + 1);
+        }
+        if node.mode == MODE_BYPASS {
+            //同位置同类型的输入优先, 否则第一个同类型的
+            let linked = |i: &&UiInput| i.link.is_some() && &i.typ == typ;
+            let input = node
+                .inputs
+                .get(*slot)
+                .filter(linked)
+                .or_else(|| node.inputs.iter().find(linked))?;
+            return self.resolve(input.link?, depth + 1);
+        }
+        Some(create_input_id(&from.to_string(), *slot))
+    }
+}
+
+impl Workflow {
+    /// 界面保存的流程, 用`/object_info`把widget值对应到输入名
+    pub fn from_ui_json(json: &str, info: &ObjectInfo) -> AppResult<Self> {
+        let ui: UiWorkflow = serde_json::from_str(json)?;
+        Self::from_nodes(ui.to_nodes(info)?)
+    }
+}
+
+#[cfg(test)]
+mod ui_tests {
+    use super::*;
+
+    fn object_info() -> ObjectInfo {
+        serde_json::from_str(fixtures::object_info()).unwrap()
+    }
+
+    #[test]
+    fn ui_should_match_api_export() {
+        let info = object_info();
+        let pairs = [
+            (fixtures::txt2img_ui(), fixtures::txt2img()),
+            (fixtures::txt2img_ui_new(), fixtures::txt2img()),
+            (fixtures::img2img_ui(), fixtures::img2img()),
+            (fixtures::meikao_ui(), fixtures::meikao()),
+        ];
+        for (ui, api) in pairs {
+            assert!(is_ui_json(ui) && !is_ui_json(api));
+            let converted = Workflow::from_ui_json(ui, &info).unwrap();
+            let expected = Workflow::from_json(api).unwrap();
+            assert_eq!(converted.nodes().count(), expected.nodes().count());
+            let converted = converted.to_json().unwrap();
+            let mut expected = expected.to_json().unwrap();
+            //没连线的widget用widget值, 旧版界面导出api时不提交
+            for text in ["text2", "text3", "text4"] {
+                let input = expected.pointer_mut(&format!("/255/inputs/{text}"));
+                if let Some(v) = input.filter(|v| v.is_null()) {
+                    *v = json!("");
+                }
+            }
+            for (id, node) in expected.as_object().unwrap() {
+                assert_eq!(&converted[id], node, "node {id}");
+            }
+        }
+    }
+
+    #[test]
+    fn ui_should_resolve_primitive_and_bypass() {
+        let info = object_info();
+        //PrimitiveNode的值直接给到select
+        let wf = Workflow::from_ui_json(fixtures::meikao_ui(), &info).unwrap();
+        let switch = wf.get_node("UpscaleSwitchAfter").unwrap();
+        let inputs = serde_json::to_value(&switch.inputs).unwrap();
+        assert_eq!(inputs["select"], 2);
+        //旁路的RepeatLatentBatch, KSampler直接接到它的输入
+        let wf = Workflow::from_ui_json(fixtures::img2img_ui(), &info).unwrap();
+        assert!(wf.by_id("214").is_err());
+        assert!(wf.dangling().is_empty());
+        let ks = wf.get_node_id("KSampler").unwrap();
+        let latent = wf
+            .upstream(ks)
+            .into_iter()
+            .find(|l| l.input == "latent_image");
+        assert_ne!(latent.unwrap().from, "214");
+    }
+
+    #[test]
+    fn ui_should_report_unknown_class() {
+        let mut info = object_info();
+        info.remove("KSampler");
+        let err = Workflow::from_ui_json(fixtures::txt2img_ui(), &info).unwrap_err();
+        assert_eq!(err.to_string(), "node 3(KSampler) not in object_info");
+    }
+}
@@ -1,8 +1,10 @@
+use macros::ComfyNode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 /// VAE decoder
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "VAEDecode")]
 pub struct VaeDecode {
     /// samples input
     pub samples: Value,
@@ -11,7 +13,8 @@ pub struct VaeDecode {
 }
 
 /// VAE encoder
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ComfyNode)]
+#[comfy(class_type = "VAEEncode")]
 pub struct VaeEncode {
     /// samples input
     pub pixels: Value,
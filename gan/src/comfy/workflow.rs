@@ -1,9 +1,63 @@
-use std::{collections::HashMap, fs};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+};
 
 use anyhow::Context;
 use serde_json::Value;
+use tracing::warn;
 
-use crate::{AppResult, Node, NODE_KSAMPLER};
+use crate::{create_input_id, id_key, is_ui_json, AppResult, Inputs, Link, Meta, Node, ObjectInfo};
+
+/// `Workflow::rem_node_with`选项
+#[derive(Debug, Clone, Default)]
+pub struct RemoveOpts {
+    /// 用到被删结点输出的输入改接到(id, slot)
+    pub rewire: Option<(String, usize)>,
+    /// 剪掉因删除而孤立的结点
+    pub prune: bool,
+}
+
+/// `Workflow::rem_node_with`结果
+#[derive(Debug, Default)]
+pub struct Removed {
+    /// 删除的结点id, 含剪掉的
+    pub ids: Vec<String>,
+    /// 已改接的连接(改接前)
+    pub rewired: Vec<Link>,
+    /// 仍然悬空的连接
+    pub dangling: Vec<Link>,
+}
+
+/// 解析api格式的结点, 不检查标题重复
+pub(crate) fn parse_nodes(nodes: HashMap<String, Value>) -> AppResult<Vec<Node>> {
+    let mut parsed = Vec::with_capacity(nodes.len());
+    for (id, node) in nodes.into_iter() {
+        let mut node: Node = serde_json::from_value(node).map_err(|e| format!("node {id} {e}"))?;
+        node.id = id;
+        parsed.push(node);
+    }
+    Ok(parsed)
+}
+
+/// 重复的标题及其结点id, 按标题排序
+pub fn dup_titles<'a>(nodes: impl IntoIterator<Item = &'a Node>) -> Vec<(String, Vec<String>)> {
+    let mut titles: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for node in nodes {
+        titles
+            .entry(&node.meta.title)
+            .or_default()
+            .push(node.id.clone());
+    }
+    titles
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .map(|(title, mut ids)| {
+            ids.sort_by(|a, b| id_key(a).cmp(&id_key(b)));
+            (title.to_owned(), ids)
+        })
+        .collect()
+}
 
 /// The comfy ui workflow
 #[derive(Debug)]
@@ -12,22 +66,35 @@ pub struct Workflow {
     id_node_map: HashMap<String, Node>,
     /// map from title to node_id, 找接入参数流程Node要求title唯一
     title_id_map: HashMap<String, String>,
+    /// `/object_info`里`output_node`的类, 剪枝时和内置的输出结点一样不剪
+    output_classes: HashSet<String>,
 }
 
 impl Workflow {
     pub fn from_json(json: &str) -> AppResult<Self> {
-        let mut id_node_map: HashMap<String, Node> = serde_json::from_str(json)?;
-        let mut title_id_map = HashMap::new();
-        for (id, node) in id_node_map.iter_mut() {
-            node.id = id.clone();
-            let title = node.meta.title.clone();
-            if title_id_map.insert(title, id.clone()).is_some() {
-                return Err(format!("dup: {} <-> {}", node.meta.title, node.class_type).into());
-            }
+        Self::from_nodes(serde_json::from_str(json)?)
+    }
+
+    /// api格式的结点 id => {inputs, class_type, _meta}
+    pub(crate) fn from_nodes(nodes: HashMap<String, Value>) -> AppResult<Self> {
+        let nodes = parse_nodes(nodes)?;
+        let dups = dup_titles(&nodes);
+        if !dups.is_empty() {
+            let dups: Vec<String> = dups
+                .iter()
+                .map(|(title, ids)| format!("{title}({})", ids.join(", ")))
+                .collect();
+            return Err(format!("dup title: {}", dups.join(", ")).into());
         }
+        let title_id_map = nodes
+            .iter()
+            .map(|n| (n.meta.title.clone(), n.id.clone()))
+            .collect();
+        let id_node_map = nodes.into_iter().map(|n| (n.id.clone(), n)).collect();
         Ok(Self {
             id_node_map,
             title_id_map,
+            output_classes: HashSet::new(),
         })
     }
 
@@ -35,7 +102,41 @@ impl Workflow {
         Self::from_json(fs::read_to_string(json_file)?.as_str())
     }
 
+    /// api格式或界面保存的格式都可以, 界面格式用`info`转换
+    pub fn load(json_file: &str, info: &ObjectInfo) -> AppResult<Self> {
+        let json = fs::read_to_string(json_file)?;
+        let mut wf = if is_ui_json(&json) {
+            Self::from_ui_json(&json, info).map_err(|e| format!("{json_file}: {e}"))?
+        } else {
+            Self::from_json(&json)?
+        };
+        wf.set_output_classes(info);
+        Ok(wf)
+    }
+
+    /// 按`/object_info`的`output_node`认输出结点, 自定义的保存视频/文本等结点也不剪
+    pub fn set_output_classes(&mut self, info: &ObjectInfo) {
+        self.output_classes = info
+            .iter()
+            .filter(|(_, n)| n.output_node)
+            .map(|(class_type, _)| class_type.clone())
+            .collect();
+    }
+
+    pub fn is_output(&self, node: &Node) -> bool {
+        node.is_output() || self.output_classes.contains(&node.class_type)
+    }
+
+    /// 有悬空连接时报错, comfy会拒绝
     pub fn to_json(&self) -> AppResult<Value> {
+        let dangling = self.dangling();
+        if !dangling.is_empty() {
+            let links: Vec<String> = dangling
+                .iter()
+                .map(|l| format!("{}.{} -> {}", l.to, l.input, l.from))
+                .collect();
+            return Err(format!("dangling: {}", links.join(", ")).into());
+        }
         serde_json::to_value(&self.id_node_map).map_err(|e| e.into())
     }
 
@@ -65,10 +166,186 @@ impl Workflow {
             .ok_or(format!("by_id: {id} not found").into())
     }
 
-    pub fn rem_node(&mut self, title: &str) {
-        if let Ok(id) = self.get_node_id(title).cloned() {
-            self.id_node_map.remove(&id);
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.id_node_map.values()
+    }
+
+    pub fn nodes_mut(&mut self) -> impl Iterator<Item = &mut Node> {
+        self.id_node_map.values_mut()
+    }
+
+    /// 删除结点, 返回悬空的连接(原来接到它输出的输入)
+    pub fn rem_node(&mut self, title: &str) -> Vec<Link> {
+        match self.rem_node_with(title, &RemoveOpts::default()) {
+            Ok(removed) => removed.dangling,
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// 删除结点, 按`opts`改接或剪掉相关结点
+    pub fn rem_node_with(&mut self, title: &str, opts: &RemoveOpts) -> AppResult<Removed> {
+        let Ok(id) = self.get_node_id(title).cloned() else {
+            return Ok(Removed::default());
+        };
+        if let Some((to_id, _)) = &opts.rewire {
+            if to_id == &id || self.by_id(to_id).is_err() {
+                return Err(format!("rewire {title}: bad target {to_id}").into());
+            }
+        }
+        let upstream: Vec<String> = self.upstream(&id).into_iter().map(|l| l.from).collect();
+        let mut removed = Removed::default();
+        self.remove_id(&id, &mut removed);
+        if let Some((to_id, slot)) = &opts.rewire {
+            for link in self.dangling().into_iter().filter(|l| l.from == id) {
+                let value = create_input_id(to_id, *slot);
+                self.by_id_mut(&link.to)?.set_input(&link.input, value)?;
+                removed.rewired.push(link);
+            }
+        }
+        if opts.prune {
+            self.prune(upstream, &mut removed);
+        }
+        removed.dangling = self
+            .dangling()
+            .into_iter()
+            .filter(|l| removed.ids.contains(&l.from))
+            .collect();
+        for link in removed.dangling.iter() {
+            warn!("dangling: {}.{} -> {}", link.to, link.input, link.from);
+        }
+        Ok(removed)
+    }
+
+    /// 新结点id, 现有最大id+1
+    pub fn next_id(&self) -> String {
+        let max = self
+            .id_node_map
+            .keys()
+            .filter_map(|id| id.parse::<u64>().ok())
+            .max()
+            .unwrap_or_default();
+        (max + 1).to_string()
+    }
+
+    /// 标题已有时依次加`_2`,`_3`...
+    pub fn unique_title(&self, title: &str) -> String {
+        let mut unique = title.to_owned();
+        let mut n = 1;
+        while self.title_id_map.contains_key(&unique) {
+            n += 1;
+            unique = format!("{title}_{n}");
+        }
+        unique
+    }
+
+    /// 加入新结点, 返回新id, 标题重复时用`unique_title`
+    pub fn add_node(&mut self, title: &str, inputs: Inputs) -> AppResult<String> {
+        let class_type = inputs
+            .class_type()
+            .ok_or_else(|| format!("add_node {title}: no class_type"))?;
+        let id = self.next_id();
+        let node = Node {
+            inputs,
+            class_type: class_type.to_owned(),
+            meta: Meta {
+                title: self.unique_title(title),
+            },
+            id: id.clone(),
+        };
+        self.insert_node(node);
+        Ok(id)
+    }
+
+    /// 调用方保证id和标题都没有用过
+    pub(crate) fn insert_node(&mut self, node: Node) {
+        self.title_id_map
+            .insert(node.meta.title.clone(), node.id.clone());
+        self.id_node_map.insert(node.id.clone(), node);
+    }
+
+    /// `from`结点第`slot`个输出的使用者都改用`to`结点第`to_slot`个输出(`to`自己除外), 返回改前的连接
+    pub fn replace_output(
+        &mut self,
+        from: &str,
+        slot: usize,
+        to: &str,
+        to_slot: usize,
+    ) -> AppResult<Vec<Link>> {
+        self.by_id(to)?;
+        let links: Vec<Link> = self
+            .downstream(from)
+            .into_iter()
+            .filter(|l| l.slot == slot && l.to != to)
+            .collect();
+        for link in links.iter() {
+            let value = create_input_id(to, to_slot);
+            self.by_id_mut(&link.to)?.set_input(&link.input, value)?;
         }
+        Ok(links)
+    }
+
+    /// 把`id`结点插到`from`结点第`slot`个输出和它的使用者之间:
+    /// `id`的`input`接`from`, 使用者改接`id`第`out_slot`个输出
+    pub fn splice(
+        &mut self,
+        id: &str,
+        input: &str,
+        from: &str,
+        slot: usize,
+        out_slot: usize,
+    ) -> AppResult<Vec<Link>> {
+        self.by_id(from)?;
+        let links = self.replace_output(from, slot, id, out_slot)?;
+        self.by_id_mut(id)?
+            .set_input(input, create_input_id(from, slot))?;
+        Ok(links)
+    }
+
+    fn remove_id(&mut self, id: &str, removed: &mut Removed) {
+        if let Some(node) = self.id_node_map.remove(id) {
+            self.title_id_map.remove(&node.meta.title);
+            removed.ids.push(id.to_owned());
+        }
+    }
+
+    /// 从删除结点的上游和使用者开始, 反复剪掉孤立结点: 没有消费者了的上游;
+    /// 或者输入接着被删结点, 又没有消费者的. 只动这次删除波及的结点, 输出结点不剪, 留着报悬空
+    fn prune(&mut self, mut candidates: Vec<String>, removed: &mut Removed) {
+        let mut expanded = 0;
+        loop {
+            let links = self.links();
+            //被删结点的使用者
+            let removed_now = &removed.ids[expanded..];
+            candidates.extend(
+                links
+                    .iter()
+                    .filter(|l| removed_now.contains(&l.from))
+                    .map(|l| l.to.clone()),
+            );
+            expanded = removed.ids.len();
+            let consumed = |id: &str| links.iter().any(|l| l.from == id);
+            let mut dead: Vec<String> = candidates
+                .drain(..)
+                .filter(|id| self.by_id(id).is_ok_and(|n| !self.is_output(n)) && !consumed(id))
+                .collect();
+            dead.sort();
+            dead.dedup();
+            if dead.is_empty() {
+                return;
+            }
+            for id in dead {
+                candidates.extend(self.upstream(&id).into_iter().map(|l| l.from));
+                self.remove_id(&id, removed);
+            }
+        }
+    }
+
+    /// 指向不存在结点的连接
+    pub fn dangling(&self) -> Vec<Link> {
+        self.links()
+            .into_iter()
+            .filter(|l| !self.id_node_map.contains_key(&l.from))
+            .collect()
     }
 
     pub fn get_node_id(&self, title: &str) -> AppResult<&String> {
@@ -78,8 +355,22 @@ impl Workflow {
             .context(format!("no id {title}"))?)
     }
 
+    /// 所有采样器都用这个种子, 分步/多采样器流程也能复现
     pub fn set_seed(&mut self, seed: i64) -> AppResult<()> {
-        self.get_node_mut(NODE_KSAMPLER)?.k_sampler_mut().seed = seed;
+        let mut found = false;
+        for node in self.id_node_map.values_mut() {
+            match &mut node.inputs {
+                Inputs::KSampler(sampler) => sampler.seed = seed,
+                Inputs::KSamplerSdxl(sampler) => sampler.noise_seed = seed,
+                Inputs::KSamplerAdvanced(sampler) => sampler.noise_seed = seed,
+                Inputs::SamplerCustom(sampler) => sampler.noise_seed = seed,
+                _ => continue,
+            }
+            found = true;
+        }
+        if !found {
+            return Err("set_seed: no sampler in workflow".to_owned().into());
+        }
         Ok(())
     }
 }
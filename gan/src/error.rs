@@ -1,9 +1,12 @@
 use anyhow::Error as AnyError;
+use reqwest::Error as HttpError;
 use serde_json::Error as JsonError;
 use thiserror::Error;
 use tokio_tungstenite::tungstenite::Error as WsError;
 use toml::de::Error as TomlError;
 
+use crate::PromptRejected;
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("IO error: {0}")]
@@ -13,7 +16,17 @@ pub enum AppError {
     #[error("TOML error: {0}")]
     Toml(#[from] TomlError),
     #[error("WebSocket error: {0}")]
-    Ws(#[from] WsError),
+    Ws(Box<WsError>),
+    #[error("HTTP error: {0}")]
+    Http(#[from] HttpError),
+    #[error("prompt rejected: {0}")]
+    Rejected(Box<PromptRejected>),
+    #[error("{title}({class_type}) not {expected}")]
+    NodeType {
+        title: String,
+        class_type: String,
+        expected: &'static str,
+    },
     #[error("{0}")]
     String(String),
     #[error("{0}")]
@@ -26,4 +39,10 @@ impl From<String> for AppError {
     }
 }
 
+impl From<WsError> for AppError {
+    fn from(e: WsError) -> Self {
+        AppError::Ws(Box::new(e))
+    }
+}
+
 pub type AppResult<T> = Result<T, AppError>;
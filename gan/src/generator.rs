@@ -1,25 +1,126 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
 use anyhow::{anyhow, Context};
 use fixtures::control_nets;
 use rand::{random, Rng};
-use serde_json::Value;
+use serde::Serialize;
+use serde_json::{json, Value};
 use tracing::{debug, trace, warn};
 
 use crate::{
     comfy_class_map, comfy_preprocessor, create_input_id, rand_element, ACtrlnet, ACtrlnetStack,
-    AEmptyImage, AIPAdapter, AImageFilter, AImageRembg, ALoraStack, ALoraStacker, AppResult,
-    AutoCfg, CnCfg, Ctrlnet, IdxControlNet, IdxLoRA, LoraCfg, LoraStack, LoraStacker, Workflow,
-    NODE_CANNY_PREPROCESSOR, NODE_CROP_IMAGE, NODE_EMPTY_LATENT, NODE_IMAGE_FILTER,
-    NODE_IMAGE_PREPROCESSOR, NODE_IMAGE_SCALESIDE, NODE_KSAMPLER,
-    NODE_LINEARTSTANDARD_PREPROCESSOR, NODE_LINEART_PREPROCESSOR, NODE_REPEAT_LATENT,
-    NODE_TEXT_CONCAT, NODE_TEXT_STRING, NODE_TILE_PREPROCESSOR,
+    AEmptyImage, AHires, AIPAdapter, AImageFilter, AImageRembg, AImageUpscale, ALoraStack,
+    ALoraStacker, ASdxl, AppError, AppResult, AutoCfg, CnCfg, Ctrlnet, IdxControlNet, IdxLoRA,
+    ImageRembg, Inputs, KSampler, KSamplerAdvanced, LoraCfg, LoraStack, LoraStacker, RemoveOpts,
+    UpscaleImage, VaeDecode, VaeEncode, Workflow, NODE_CANNY_PREPROCESSOR, NODE_CROP_IMAGE,
+    NODE_EMPTY_LATENT, NODE_IMAGE_FILTER, NODE_IMAGE_PREPROCESSOR, NODE_IMAGE_SCALESIDE,
+    NODE_KSAMPLER, NODE_LINEARTSTANDARD_PREPROCESSOR, NODE_LINEART_PREPROCESSOR, NODE_LOAD_IMAGE,
+    NODE_REPEAT_LATENT, NODE_TEXT_CONCAT, NODE_TEXT_STRING, NODE_TILE_PREPROCESSOR,
+    NODE_VAE_DECODE,
 };
 
 const STEP_F32: f32 = 0.05;
 
 type TargetSize = (u16, u16);
 
+/// 一次`Generator::rand`选中的参数
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GenParams {
+    pub ckpt_name: String,
+    pub vae_name: String,
+    pub clip_skip: i8,
+    pub positive: String,
+    pub negative: String,
+    pub width: u16,
+    pub height: u16,
+    pub batch_size: u8,
+    pub sampler: SamplerParams,
+    pub loras: Vec<LoraCfg>,
+    pub ctrlnets: Vec<CnCfg>,
+    /// 图生图输入图
+    pub image: Option<String>,
+    pub ip_adapter: Option<IpAdapterParams>,
+    /// 打标模型
+    pub tagger: Option<String>,
+    /// 去背景模型
+    pub rembg: Option<String>,
+    /// 放大
+    pub upscale: Option<UpscaleParams>,
+    pub sdxl: Option<SdxlParams>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UpscaleParams {
+    pub upscale_model: String,
+    pub mode: String,
+    pub rescale_factor: f32,
+    pub resampling_method: String,
+    pub supersample: String,
+    pub hires: Option<HiresParams>,
+}
+
+/// 放大后再采样
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HiresParams {
+    pub steps: u8,
+    pub denoise: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SdxlParams {
+    pub refiner_ckpt_name: String,
+    pub refiner_clip_skip: i8,
+    pub positive_ascore: f32,
+    pub negative_ascore: f32,
+    pub refiner_steps: u8,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SamplerParams {
+    pub steps: u8,
+    pub cfg: f32,
+    pub denoise: f32,
+    pub sampler_name: String,
+    pub scheduler: String,
+    /// KSamplerAdvanced/SamplerCustom才有, 没配的为None
+    pub advanced: Option<AdvancedParams>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AdvancedParams {
+    pub add_noise: Option<String>,
+    pub start_at_step: Option<u16>,
+    pub end_at_step: Option<u16>,
+    pub return_with_leftover_noise: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IpAdapterParams {
+    pub image: String,
+    pub weight: f32,
+    pub noise: f32,
+    pub start_at: f32,
+    pub end_at: f32,
+}
+
+/// 一个prompt的完整出图记录, 图片旁边的sidecar
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GenRecord {
+    pub auto_cfg: String,
+    pub workflow: String,
+    pub cfg_idx: usize,
+    pub total_idx: usize,
+    pub seed: i64,
+    pub params: GenParams,
+}
+
+impl GenRecord {
+    /// 提交时放到`extra_data.extra_pnginfo`, Image Save写进png的`gan`文本块
+    pub fn extra_pnginfo(&self) -> Value {
+        json!({ "gan": self })
+    }
+}
+
 pub struct Generator {
     cns: HashMap<String, Ctrlnet>,
 }
@@ -31,33 +132,45 @@ impl Generator {
         }
     }
 
-    pub fn rand(&self, wf: &mut Workflow, ac: &AutoCfg, idx: usize) -> AppResult<()> {
-        self.rand_sampler(wf, ac)?;
-        self.rand_lora(wf, ac)?;
-        let land = self.rand_images(wf, ac, idx)?;
-        let size = self.rand_efficient(wf, ac, land)?;
-        self.rand_cn(wf, ac, size)?;
+    pub fn rand(&self, wf: &mut Workflow, ac: &AutoCfg, idx: usize) -> AppResult<GenParams> {
+        let mut params = GenParams::default();
+        for fragment in ac.fragments.iter() {
+            wf.attach_cfg(fragment)?;
+        }
+        self.rand_sampler(wf, ac, &mut params)?;
+        self.rand_lora(wf, ac, &mut params)?;
+        let land = self.rand_images(wf, ac, idx, &mut params)?;
+        let size = self.rand_efficient(wf, ac, land, &mut params)?;
+        self.rand_cn(wf, ac, size, &mut params)?;
         if let Some(aif) = &ac.image_filter {
             self.apply_filter(wf, aif)?;
         }
         if let Some(arembg) = &ac.image_rembg {
-            self.apply_rembg(wf, arembg)?;
+            self.apply_rembg(wf, arembg, &mut params)?;
         }
         if let Some(aipa) = &ac.ip_adapter {
-            self.apply_ip_adapter(wf, aipa)?;
+            self.apply_ip_adapter(wf, aipa, &mut params)?;
         }
         if let Some(aifa) = &ac.image_filter_after {
             self.apply_filter(wf, aifa)?;
         }
-        Ok(())
+        if let Some(aupscale) = &ac.image_upscale {
+            self.apply_upscale(wf, ac, aupscale, &mut params)?;
+        }
+        Ok(params)
     }
 
-    fn apply_ip_adapter(&self, wf: &mut Workflow, aipa: &AIPAdapter) -> AppResult<()> {
+    fn apply_ip_adapter(
+        &self,
+        wf: &mut Workflow,
+        aipa: &AIPAdapter,
+        params: &mut GenParams,
+    ) -> AppResult<()> {
         if !aipa.switch {
             return Ok(());
         }
         let ipa_node = wf.get_node_mut(&aipa.title).context("ip_adapter")?;
-        let ipa = ipa_node.i_p_adapter_apply_mut();
+        let ipa = ipa_node.try_i_p_adapter_apply_mut()?;
         let weight = rand_f32(aipa.weight_min, aipa.weight_max);
         let noise = rand_f32(aipa.noise_min, aipa.noise_max);
         let start = rand_f32(aipa.start_min, aipa.start_max);
@@ -80,37 +193,158 @@ impl Generator {
         // KSampler.model = ipa
         wf.get_node_mut(NODE_KSAMPLER)
             .context("ip_adapter")?
-            .k_sampler_mut()
+            .try_k_sampler_mut()?
             .model = Some(create_input_id(&ipa_id, 0));
         // ipa image
-        wf.by_id_mut(&img_id)?.load_image_mut().image = image.clone();
+        wf.by_id_mut(&img_id)?.try_load_image_mut()?.image = image.clone();
+        params.ip_adapter = Some(IpAdapterParams {
+            image: image.clone(),
+            weight,
+            noise,
+            start_at: start,
+            end_at: end,
+        });
         Ok(())
     }
 
-    fn apply_rembg(&self, wf: &mut Workflow, arembg: &AImageRembg) -> AppResult<()> {
+    fn apply_rembg(
+        &self,
+        wf: &mut Workflow,
+        arembg: &AImageRembg,
+        params: &mut GenParams,
+    ) -> AppResult<()> {
         if !arembg.switch {
             return Ok(());
         }
-        let rembg_node = wf.get_node_mut(&arembg.title).context("rembg")?;
-        let rembg = rembg_node.image_rembg_mut();
+        params.rembg = Some(arembg.model_name.clone());
+        let Ok(rembg_node) = wf.get_node_mut(&arembg.title) else {
+            //流程里没有, 加在LoadImage后面, 用到原图的都改用去背景的图
+            let rembg = ImageRembg {
+                model_name: arembg.model_name.clone(),
+                image: None,
+            };
+            let rembg_id = wf.add_node(&arembg.title, Inputs::ImageRembg(rembg))?;
+            let load_id = wf.get_node_id(NODE_LOAD_IMAGE)?.clone();
+            let links = wf.splice(&rembg_id, "image", &load_id, 0, 0)?;
+            debug!("rembg-{rembg_id} added after {load_id}: {links:?}");
+            return Ok(());
+        };
+        let rembg = rembg_node.try_image_rembg_mut()?;
         rembg.model_name = arembg.model_name.clone();
         let rembg_id = rembg_node.id.clone();
         //新版本增加的`ImageRembg`结点,认为有一定有`CropImage`结点
         let crop = wf
             .get_node_mut(NODE_CROP_IMAGE)
-            .context("rembg needs filter")?
-            .image_filter_mut();
+            .context("rembg needs crop")?
+            .try_crop_image_mut()?;
         crop.image = Some(create_input_id(&rembg_id, 0));
         Ok(())
     }
 
+    fn apply_upscale(
+        &self,
+        wf: &mut Workflow,
+        ac: &AutoCfg,
+        aupscale: &AImageUpscale,
+        params: &mut GenParams,
+    ) -> AppResult<()> {
+        if !aupscale.switch {
+            return Ok(());
+        }
+        let upscale = UpscaleImage {
+            upscale_model: rand_element(&aupscale.upscale_model).clone(),
+            mode: rand_element(&aupscale.mode).clone(),
+            rescale_factor: rand_f32(aupscale.rescale_factor_min, aupscale.rescale_factor_max),
+            resize_width: aupscale.resize_width,
+            resampling_method: rand_element(&aupscale.resampling_method).clone(),
+            supersample: rand_element(&aupscale.supersample).clone(),
+            rounding_modulus: aupscale.rounding_modulus,
+            image: None,
+        };
+        debug!("apply_upscale: {upscale:?}");
+        let mut up_params = UpscaleParams {
+            upscale_model: upscale.upscale_model.clone(),
+            mode: upscale.mode.clone(),
+            rescale_factor: upscale.rescale_factor,
+            resampling_method: upscale.resampling_method.clone(),
+            supersample: upscale.supersample.clone(),
+            hires: None,
+        };
+        let upscale_id = match wf.get_node_mut(&aupscale.title) {
+            Ok(upscale_node) => {
+                let image = upscale_node.try_upscale_image_mut()?.image.take();
+                *upscale_node.try_upscale_image_mut()? = UpscaleImage { image, ..upscale };
+                upscale_node.id.clone()
+            }
+            Err(_) => {
+                //流程里没有, 加在VaeDecode后面, 保存和后处理都用放大后的图
+                let upscale_id = wf.add_node(&aupscale.title, Inputs::UpscaleImage(upscale))?;
+                let decode_id = wf.get_node_id(NODE_VAE_DECODE)?.clone();
+                let links = wf.splice(&upscale_id, "image", &decode_id, 0, 0)?;
+                debug!("upscale-{upscale_id} added after {decode_id}: {links:?}");
+                upscale_id
+            }
+        };
+        if let Some(ahires) = &aupscale.hires {
+            up_params.hires = Some(self.apply_hires(wf, ac, ahires, &upscale_id)?);
+        }
+        params.upscale = Some(up_params);
+        Ok(())
+    }
+
+    /// 放大图重绘, 流程里没有`ahires.title`时加VAEEncode -> KSampler -> VAEDecode
+    fn apply_hires(
+        &self,
+        wf: &mut Workflow,
+        ac: &AutoCfg,
+        ahires: &AHires,
+        upscale_id: &str,
+    ) -> AppResult<HiresParams> {
+        let hires = HiresParams {
+            steps: rand_num(ahires.steps_min, ahires.steps_max),
+            denoise: rand_f32(ahires.denoise_min, ahires.denoise_max),
+        };
+        debug!("apply_hires: {hires:?}");
+        if let Ok(node) = wf.get_node_mut(&ahires.title) {
+            let sampler = node.try_k_sampler_mut()?;
+            sampler.steps = hires.steps;
+            sampler.denoise = hires.denoise;
+            return Ok(hires);
+        }
+        //采样参数, 模型和提示词都跟主采样器
+        let mut sampler = hires_sampler(wf, &ac.sampler.title)?;
+        sampler.steps = hires.steps;
+        sampler.denoise = hires.denoise;
+        sampler.latent_image = None;
+        let vae = wf.get_node(NODE_VAE_DECODE)?.try_vae_decode()?.vae.clone();
+        let encode = VaeEncode {
+            pixels: Value::Null,
+            vae: vae.clone(),
+        };
+        let decode = VaeDecode {
+            samples: Value::Null,
+            vae,
+        };
+        //依次接在放大结点后面, 原来用放大图的最后都改用重绘的图
+        let encode_title = format!("{}VaeEncode", ahires.title);
+        let encode_id = wf.add_node(&encode_title, Inputs::VaeEncode(encode))?;
+        wf.splice(&encode_id, "pixels", upscale_id, 0, 0)?;
+        let sampler_id = wf.add_node(&ahires.title, Inputs::KSampler(sampler))?;
+        wf.splice(&sampler_id, "latent_image", &encode_id, 0, 0)?;
+        let decode_title = format!("{}VaeDecode", ahires.title);
+        let decode_id = wf.add_node(&decode_title, Inputs::VaeDecode(decode))?;
+        let links = wf.splice(&decode_id, "samples", &sampler_id, 0, 0)?;
+        debug!("hires {encode_id}->{sampler_id}->{decode_id} added after {upscale_id}: {links:?}");
+        Ok(hires)
+    }
+
     fn apply_filter(&self, wf: &mut Workflow, aif: &AImageFilter) -> AppResult<()> {
         if !aif.switch {
             return Ok(());
         }
         let filter_node = wf.get_node_mut(&aif.title).context("filter")?;
         trace!("apply_filter {} ...", filter_node.id);
-        let filter = filter_node.image_filter_mut();
+        let filter = filter_node.try_image_filter_mut()?;
         if let Some(brightness) = aif.brightness {
             filter.brightness = brightness;
         }
@@ -145,7 +379,13 @@ impl Generator {
         Ok(())
     }
 
-    fn rand_efficient(&self, wf: &mut Workflow, ac: &AutoCfg, land: bool) -> AppResult<TargetSize> {
+    fn rand_efficient(
+        &self,
+        wf: &mut Workflow,
+        ac: &AutoCfg,
+        land: bool,
+        params: &mut GenParams,
+    ) -> AppResult<TargetSize> {
         let ec = &ac.efficient;
         //land交换w,h
         let (w, h) = if land {
@@ -155,24 +395,70 @@ impl Generator {
         };
         //调整输入规格
         if let Ok(scale) = wf.get_node_mut(NODE_IMAGE_SCALESIDE) {
-            let scale = scale.image_scale_side_mut();
+            let scale = scale.try_image_scale_side_mut()?;
             scale.side_length = if w > h { w } else { h };
         }
-        let efficient = wf.get_node_mut(&ec.title)?.efficient_loader_mut();
-        efficient.negative = rand_element(&ec.negative).clone();
-        efficient.batch_size = ec.batch_size;
-        efficient.empty_latent_width = w;
-        efficient.empty_latent_height = h;
-        efficient.vae_name = rand_element(&ec.vae_name).clone();
-        efficient.ckpt_name = ec.ckpt_name.clone();
-        efficient.clip_skip = *rand_element(&ec.clip_skip);
-        efficient.weight_interpretation = ec.weight_interpretation.clone();
+        let negative = rand_element(&ec.negative).clone();
+        let vae_name = rand_element(&ec.vae_name).clone();
+        let clip_skip = *rand_element(&ec.clip_skip);
         let positive: &String = rand_element(&ec.positive);
-        efficient.positive = Value::String(positive.into());
+        let node = wf.get_node_mut(&ec.title)?;
+        //配置和流程的加载器要对得上, 不然精炼/ControlNet参数会配错
+        macro_rules! set_shared {
+            ($efficient:ident) => {
+                $efficient.negative = negative.clone();
+                $efficient.batch_size = ec.batch_size;
+                $efficient.empty_latent_width = w;
+                $efficient.empty_latent_height = h;
+                $efficient.vae_name = vae_name.clone();
+                $efficient.weight_interpretation = ec.weight_interpretation.clone();
+                $efficient.positive = Value::String(positive.into());
+            };
+        }
+        match (&mut node.inputs, &ec.sdxl) {
+            (Inputs::EfficientLoader(efficient), None) => {
+                set_shared!(efficient);
+                efficient.ckpt_name = ec.ckpt_name.clone();
+                efficient.clip_skip = clip_skip;
+            }
+            (Inputs::EfficientLoaderSdxl(efficient), Some(_)) => {
+                set_shared!(efficient);
+                efficient.base_ckpt_name = ec.ckpt_name.clone();
+                efficient.base_clip_skip = clip_skip;
+            }
+            (Inputs::EfficientLoader(_), Some(_)) => {
+                return Err(
+                    format!("{}: [efficient.sdxl] needs EfficientLoaderSdxl", ec.title).into(),
+                )
+            }
+            (Inputs::EfficientLoaderSdxl(_), None) => {
+                return Err(
+                    format!("{}: EfficientLoaderSdxl needs [efficient.sdxl]", ec.title).into(),
+                )
+            }
+            _ => {
+                return Err(AppError::NodeType {
+                    title: ec.title.clone(),
+                    class_type: node.class_type.clone(),
+                    expected: "EfficientLoader|EfficientLoaderSdxl",
+                })
+            }
+        }
+        if let Some(asdxl) = &ec.sdxl {
+            self.rand_sdxl(wf, ac, asdxl, params)?;
+        }
+        params.ckpt_name = ec.ckpt_name.clone();
+        params.vae_name = vae_name;
+        params.clip_skip = clip_skip;
+        params.positive = positive.clone();
+        params.negative = negative;
+        params.width = w;
+        params.height = h;
+        params.batch_size = ec.batch_size;
         //提示词及图标打标
         if let Ok(ts_node) = wf.get_node_mut(NODE_TEXT_STRING) {
             //支持Tagger的版本
-            let ts = ts_node.text_string_mut();
+            let ts = ts_node.try_text_string_mut()?;
             ts.text = positive.into();
             let mut ts_id = ts_node.id.clone();
             let atagger = ac.tagger.clone().unwrap_or_default();
@@ -180,41 +466,48 @@ impl Generator {
                 //有自动打标
                 let if_id = wf.get_node_id(NODE_IMAGE_FILTER)?.clone();
                 let tagger_node = wf.get_node_mut(&atagger.title)?;
-                let tagger = tagger_node.tagger_mut();
+                let tagger = tagger_node.try_tagger_mut()?;
                 tagger.model = atagger.model.clone();
                 //Tagger.image = ImageFilter
                 tagger.image = Some(create_input_id(&if_id, 0));
                 let tagger_id = tagger_node.id.clone();
                 let concat_node = wf.get_node_mut(NODE_TEXT_CONCAT)?;
-                let concat = concat_node.text_concat_mut();
+                let concat = concat_node.try_text_concat_mut()?;
                 //TextConcat.text2 = Tagger
                 concat.text2 = Some(create_input_id(&tagger_id, 0));
                 ts_id = concat_node.id.clone();
                 trace!("Tagger-{tagger_id}.image={if_id}, TextConcat{ts_id}.text2={tagger_id}");
-            } else {
-                //无自动打标, 移除Tagger结点
-                wf.rem_node(&atagger.title);
+                params.tagger = Some(atagger.model.clone());
             }
             //EfficientLoader.positive = Text
             trace!("EfficientLoader.positive={ts_id}");
-            wf.get_node_mut(&ec.title)?.efficient_loader_mut().positive =
-                create_input_id(&ts_id, 0);
+            wf.get_node_mut(&ec.title)?
+                .set_input("positive", create_input_id(&ts_id, 0))?;
+            if !atagger.switch {
+                //无自动打标, 移除Tagger结点及没用了的TextConcat
+                let opts = RemoveOpts {
+                    prune: true,
+                    ..Default::default()
+                };
+                let removed = wf.rem_node_with(&atagger.title, &opts)?;
+                trace!("removed {:?}", removed.ids);
+            }
         }
         //图生图 用CropImage调整生图大小, 用RepeatLatent控制批次
         if let Ok(crop) = wf.get_node_mut(NODE_CROP_IMAGE) {
-            let crop = crop.crop_image_mut();
+            let crop = crop.try_crop_image_mut()?;
             crop.target_w = w;
             crop.target_h = h;
             trace!("img2img: w={}, h={}", crop.target_w, crop.target_h);
         }
         if let Ok(repeat) = wf.get_node_mut(NODE_REPEAT_LATENT) {
-            let repeat = repeat.repeat_latent_mut();
+            let repeat = repeat.try_repeat_latent_mut()?;
             repeat.amount = ec.batch_size;
             trace!("img2img: batch_size={}", repeat.amount);
         }
         //文生图 用EmptyLatent控制生图大小,批次
         if let Ok(latent) = wf.get_node_mut(NODE_EMPTY_LATENT) {
-            let latent = latent.empty_latent_mut();
+            let latent = latent.try_empty_latent_mut()?;
             latent.width = w;
             latent.height = h;
             latent.batch_size = ec.batch_size;
@@ -222,18 +515,62 @@ impl Generator {
         }
         //图生图纯色图输入
         if let Some(aemc) = &ac.empty_image {
-            self.apply_empty(aemc, (w, h), ec.batch_size, wf);
+            self.apply_empty(aemc, (w, h), ec.batch_size, wf)?;
         }
 
         Ok((w, h))
     }
 
-    fn apply_empty(&self, aemc: &AEmptyImage, (w, h): TargetSize, bs: u8, wf: &mut Workflow) {
+    /// 精炼模型及评分, 采样器最后`refiner_steps`步换精炼模型
+    fn rand_sdxl(
+        &self,
+        wf: &mut Workflow,
+        ac: &AutoCfg,
+        asdxl: &ASdxl,
+        params: &mut GenParams,
+    ) -> AppResult<()> {
+        let sdxl = SdxlParams {
+            refiner_ckpt_name: rand_element(&asdxl.refiner_ckpt_name).clone(),
+            refiner_clip_skip: *rand_element(&asdxl.refiner_clip_skip),
+            positive_ascore: rand_f32(asdxl.positive_ascore_min, asdxl.positive_ascore_max),
+            negative_ascore: rand_f32(asdxl.negative_ascore_min, asdxl.negative_ascore_max),
+            refiner_steps: *rand_element(&asdxl.refiner_steps),
+        };
+        let efficient = wf
+            .get_node_mut(&ac.efficient.title)?
+            .try_efficient_loader_sdxl_mut()?;
+        efficient.refiner_ckpt_name = sdxl.refiner_ckpt_name.clone();
+        efficient.refiner_clip_skip = sdxl.refiner_clip_skip;
+        efficient.positive_ascore = sdxl.positive_ascore;
+        efficient.negative_ascore = sdxl.negative_ascore;
+        let sampler = wf
+            .get_node_mut(&ac.sampler.title)?
+            .try_k_sampler_sdxl_mut()?;
+        let refine = sdxl.refiner_steps > 0 && sdxl.refiner_ckpt_name != "None";
+        sampler.refine_at_step = match refine {
+            true => sampler.steps.saturating_sub(sdxl.refiner_steps) as i16,
+            false => -1,
+        };
+        debug!(
+            "rand_sdxl: {sdxl:?}, refine_at_step={}",
+            sampler.refine_at_step
+        );
+        params.sdxl = Some(sdxl);
+        Ok(())
+    }
+
+    fn apply_empty(
+        &self,
+        aemc: &AEmptyImage,
+        (w, h): TargetSize,
+        bs: u8,
+        wf: &mut Workflow,
+    ) -> AppResult<()> {
         if !aemc.switch {
-            return;
+            return Ok(());
         }
         if let Ok(empty) = wf.get_node_mut(&aemc.title) {
-            let empty = empty.empty_image_mut();
+            let empty = empty.try_empty_image_mut()?;
             empty.width = w;
             empty.height = h;
             empty.batch_size = bs;
@@ -242,24 +579,37 @@ impl Generator {
         } else {
             warn!("{} not found", aemc.title);
         }
+        Ok(())
     }
 
     // 图片名以`land_`开头, 返回`true`
-    fn rand_images(&self, wf: &mut Workflow, ac: &AutoCfg, idx: usize) -> AppResult<bool> {
+    fn rand_images(
+        &self,
+        wf: &mut Workflow,
+        ac: &AutoCfg,
+        idx: usize,
+        params: &mut GenParams,
+    ) -> AppResult<bool> {
         let mut land = false;
         let img_name = if let Some(cfg) = &ac.load_image {
             let imgs = &cfg.images;
-            let img_name = imgs.get(idx % imgs.len()).context("get img")?;
+            let img_path = imgs.get(idx % imgs.len()).context("get img")?;
+            //本地文件提交前才上传, 这里按文件名判断横竖, 取输出前缀
+            let img_name = Path::new(img_path)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .context("get img_name")?;
             if img_name.starts_with("land_") {
                 land = true;
             }
-            wf.get_node_mut(&cfg.title)?.load_image_mut().image = img_name.clone();
+            wf.get_node_mut(&cfg.title)?.try_load_image_mut()?.image = img_path.clone();
+            params.image = Some(img_path.clone());
             img_name
         } else {
             &ac.save_image.filename_prefix
         };
         let save = &ac.save_image;
-        let saver = wf.get_node_mut(&save.title)?.image_save_mut();
+        let saver = wf.get_node_mut(&save.title)?.try_image_save_mut()?;
         saver.filename_prefix = img_name
             .split('.')
             .next()
@@ -269,16 +619,26 @@ impl Generator {
         Ok(land)
     }
 
-    fn rand_cn(&self, wf: &mut Workflow, ac: &AutoCfg, size: TargetSize) -> AppResult<()> {
+    fn rand_cn(
+        &self,
+        wf: &mut Workflow,
+        ac: &AutoCfg,
+        size: TargetSize,
+        params: &mut GenParams,
+    ) -> AppResult<()> {
         if let Some(acn) = &ac.ctrlnet_stack {
             if acn.switch() {
+                let sdxl = matches!(
+                    wf.get_node(&ac.efficient.title)?.inputs,
+                    Inputs::EfficientLoaderSdxl(_)
+                );
                 wf.get_node_mut(acn.title.as_str())?
-                    .ctrlnet_stack_mut()
+                    .try_ctrlnet_stack_mut()?
                     .disable_all();
 
-                self.rand_cn1(wf, acn, size)?;
-                self.rand_cn2(wf, acn, size)?;
-                self.rand_cn3(wf, acn, size)?;
+                self.rand_cn1(wf, acn, size, sdxl, params)?;
+                self.rand_cn2(wf, acn, size, sdxl, params)?;
+                self.rand_cn3(wf, acn, size, sdxl, params)?;
             }
         }
         Ok(())
@@ -298,7 +658,7 @@ impl Generator {
         match my_processor_name {
             NODE_LINEART_PREPROCESSOR => {
                 // realistic|coarse
-                let processor = processor_node.line_art_preprocessor_mut();
+                let processor = processor_node.try_line_art_preprocessor_mut()?;
                 match cfg.my_name.as_str() {
                     "realistic" => {
                         processor.coarse = "disable".into();
@@ -323,12 +683,12 @@ impl Generator {
             }
             NODE_LINEARTSTANDARD_PREPROCESSOR => {
                 // resolution
-                let processor = processor_node.lineart_standard_preprocessor_mut();
+                let processor = processor_node.try_lineart_standard_preprocessor_mut()?;
                 processor.resolution = cfg.resolution;
             }
             NODE_TILE_PREPROCESSOR => {
                 // resolution
-                let processor = processor_node.tile_preprocessor_mut();
+                let processor = processor_node.try_tile_preprocessor_mut()?;
                 processor.resolution = cfg.resolution;
                 processor.pyrup_iters = if acn_stack.tile_pyrup_iters > 0 {
                     acn_stack.tile_pyrup_iters
@@ -338,12 +698,12 @@ impl Generator {
             }
             NODE_IMAGE_PREPROCESSOR => {
                 // resolution
-                let processor = processor_node.image_preprocessor_mut();
+                let processor = processor_node.try_image_preprocessor_mut()?;
                 processor.resolution = cfg.resolution;
                 processor.preprocessor = cfg.preprocessor.clone();
             }
             NODE_CANNY_PREPROCESSOR => {
-                let processor = processor_node.canny_edge_preprocessor_mut();
+                let processor = processor_node.try_canny_edge_preprocessor_mut()?;
                 processor.resolution = cfg.resolution;
                 if acn_stack.canny_low_threshold > 0 {
                     processor.low_threshold = acn_stack.canny_low_threshold;
@@ -360,60 +720,96 @@ impl Generator {
         Ok(processor_node.id.clone())
     }
 
-    fn rand_cn1(&self, wf: &mut Workflow, acn: &ACtrlnetStack, size: TargetSize) -> AppResult<()> {
+    fn rand_cn1(
+        &self,
+        wf: &mut Workflow,
+        acn: &ACtrlnetStack,
+        size: TargetSize,
+        sdxl: bool,
+        params: &mut GenParams,
+    ) -> AppResult<()> {
         let idx = IdxControlNet::ControlNet1;
         if let Some(acfg) = acn.cfg(&idx) {
-            let cfg = self.rand_cn_cfg(&acfg, size)?;
+            let cfg = self.rand_cn_cfg(&acfg, size, sdxl)?;
             debug!("rand_cn1 acfg={acfg:?}, cn_cfg={cfg:?}");
             wf.get_node_mut(acn.title.as_str())?
-                .ctrlnet_stack_mut()
+                .try_ctrlnet_stack_mut()?
                 .enable(idx, &cfg);
             let id = self.rand_preprocessor(wf, &cfg, acn)?;
             wf.get_node_mut(acn.title.as_str())?
-                .ctrlnet_stack_mut()
+                .try_ctrlnet_stack_mut()?
                 .image_1 = Some(create_input_id(&id, 0));
+            params.ctrlnets.push(cfg);
         }
         Ok(())
     }
 
-    fn rand_cn2(&self, wf: &mut Workflow, acn: &ACtrlnetStack, size: TargetSize) -> AppResult<()> {
+    fn rand_cn2(
+        &self,
+        wf: &mut Workflow,
+        acn: &ACtrlnetStack,
+        size: TargetSize,
+        sdxl: bool,
+        params: &mut GenParams,
+    ) -> AppResult<()> {
         let idx = IdxControlNet::ControlNet2;
         if let Some(acfg) = acn.cfg(&idx) {
-            let cfg = self.rand_cn_cfg(&acfg, size)?;
+            let cfg = self.rand_cn_cfg(&acfg, size, sdxl)?;
             debug!("rand_cn2 acfg={acfg:?}, cn_cfg={cfg:?}");
             wf.get_node_mut(acn.title.as_str())?
-                .ctrlnet_stack_mut()
+                .try_ctrlnet_stack_mut()?
                 .enable(idx, &cfg);
             let id = self.rand_preprocessor(wf, &cfg, acn)?;
             wf.get_node_mut(acn.title.as_str())?
-                .ctrlnet_stack_mut()
+                .try_ctrlnet_stack_mut()?
                 .image_2 = Some(create_input_id(&id, 0));
+            params.ctrlnets.push(cfg);
         }
         Ok(())
     }
 
-    fn rand_cn3(&self, wf: &mut Workflow, acn: &ACtrlnetStack, size: TargetSize) -> AppResult<()> {
+    fn rand_cn3(
+        &self,
+        wf: &mut Workflow,
+        acn: &ACtrlnetStack,
+        size: TargetSize,
+        sdxl: bool,
+        params: &mut GenParams,
+    ) -> AppResult<()> {
         let idx = IdxControlNet::ControlNet3;
         if let Some(acfg) = acn.cfg(&idx) {
-            let cfg = self.rand_cn_cfg(&acfg, size)?;
+            let cfg = self.rand_cn_cfg(&acfg, size, sdxl)?;
             debug!("rand_cn3 acfg={acfg:?}, cn_cfg={cfg:?}");
             wf.get_node_mut(acn.title.as_str())?
-                .ctrlnet_stack_mut()
+                .try_ctrlnet_stack_mut()?
                 .enable(idx, &cfg);
             let id = self.rand_preprocessor(wf, &cfg, acn)?;
             wf.get_node_mut(acn.title.as_str())?
-                .ctrlnet_stack_mut()
+                .try_ctrlnet_stack_mut()?
                 .image_3 = Some(create_input_id(&id, 0));
+            params.ctrlnets.push(cfg);
         }
         Ok(())
     }
 
-    fn rand_cn_cfg(&self, acfg: &ACtrlnet, size: TargetSize) -> AppResult<CnCfg> {
-        let ctrl_type = rand_element(&acfg.ctrl_type);
-        let cn = self
-            .cns
-            .get(ctrl_type)
-            .ok_or(anyhow!("no cn type {ctrl_type}"))?;
+    /// `sdxl`时只选SDXL的CN类型, 否则只选SD1.5的
+    fn rand_cn_cfg(&self, acfg: &ACtrlnet, size: TargetSize, sdxl: bool) -> AppResult<CnCfg> {
+        for ctrl_type in acfg.ctrl_type.iter() {
+            if !self.cns.contains_key(ctrl_type) {
+                return Err(anyhow!("no cn type {ctrl_type}").into());
+            }
+        }
+        let ctrl_types: Vec<&String> = acfg
+            .ctrl_type
+            .iter()
+            .filter(|t| self.cns[*t].sdxl == sdxl)
+            .collect();
+        if ctrl_types.is_empty() {
+            let model = if sdxl { "SDXL" } else { "SD1.5" };
+            return Err(format!("no {model} cn type in {:?}", acfg.ctrl_type).into());
+        }
+        let ctrl_type = *rand_element(&ctrl_types);
+        let cn = &self.cns[ctrl_type];
         let weight = rand_f32(acfg.strength_min, acfg.strength_max);
         let start = rand_f32(acfg.start_min, acfg.start_max);
         let end = rand_f32(acfg.end_min, acfg.end_max);
@@ -441,9 +837,13 @@ impl Generator {
         })
     }
 
-    fn rand_sampler(&self, wf: &mut Workflow, ac: &AutoCfg) -> AppResult<()> {
+    fn rand_sampler(
+        &self,
+        wf: &mut Workflow,
+        ac: &AutoCfg,
+        params: &mut GenParams,
+    ) -> AppResult<()> {
         let asampler = &ac.sampler;
-        let sampler = wf.get_node_mut(&asampler.title)?.k_sampler_mut();
         let seed = random::<u32>() as i64;
         let steps = rand_num(asampler.steps_min, asampler.steps_max);
         let cfg = rand_f32(asampler.cfg_min, asampler.cfg_max);
@@ -451,37 +851,189 @@ impl Generator {
         let sampler_name = rand_element(&asampler.sampler_name);
         let scheduler = rand_element(&asampler.scheduler);
         debug!("rand_sampler: seed={seed}, steps={steps}, cfg={cfg}, denoise={denoise}, {sampler_name}-{scheduler}");
-        sampler.seed = seed;
-        sampler.steps = steps;
-        sampler.cfg = cfg;
-        sampler.denoise = denoise;
-        sampler.sampler_name = sampler_name.clone();
-        sampler.scheduler = scheduler.clone();
+        let aadv = asampler.advanced.clone().unwrap_or_default();
+        let rand_opt = |vs: &[String]| (!vs.is_empty()).then(|| rand_element(vs).clone());
+        let rand_step = |vs: &[u16]| (!vs.is_empty()).then(|| *rand_element(vs));
+        let node = wf.get_node_mut(&asampler.title)?;
+        let mut advanced = None;
+        match &mut node.inputs {
+            Inputs::KSampler(sampler) => {
+                sampler.seed = seed;
+                sampler.steps = steps;
+                sampler.cfg = cfg;
+                sampler.denoise = denoise;
+                sampler.sampler_name = sampler_name.clone();
+                sampler.scheduler = scheduler.clone();
+            }
+            Inputs::KSamplerSdxl(sampler) => {
+                //SDXL采样器没有denoise
+                sampler.noise_seed = seed;
+                sampler.steps = steps;
+                sampler.cfg = cfg;
+                sampler.sampler_name = sampler_name.clone();
+                sampler.scheduler = scheduler.clone();
+            }
+            Inputs::KSamplerAdvanced(sampler) => {
+                //分步采样用start/end_at_step, 没有denoise
+                sampler.noise_seed = seed;
+                sampler.steps = steps;
+                sampler.cfg = cfg;
+                sampler.sampler_name = sampler_name.clone();
+                sampler.scheduler = scheduler.clone();
+                let adv = AdvancedParams {
+                    add_noise: rand_opt(&aadv.add_noise),
+                    start_at_step: rand_step(&aadv.start_at_step),
+                    end_at_step: rand_step(&aadv.end_at_step),
+                    return_with_leftover_noise: rand_opt(&aadv.return_with_leftover_noise),
+                };
+                if let Some(add_noise) = &adv.add_noise {
+                    sampler.add_noise = add_noise.clone();
+                }
+                if let Some(start) = adv.start_at_step {
+                    sampler.start_at_step = start;
+                }
+                if let Some(end) = adv.end_at_step {
+                    sampler.end_at_step = end;
+                }
+                if let Some(leftover) = &adv.return_with_leftover_noise {
+                    sampler.return_with_leftover_noise = leftover.clone();
+                }
+                check_split_steps(&node.meta.title, sampler)?;
+                advanced = Some(adv);
+            }
+            Inputs::SamplerCustom(sampler) => {
+                sampler.noise_seed = seed;
+                sampler.cfg = cfg;
+                let adv = AdvancedParams {
+                    add_noise: rand_opt(&aadv.add_noise),
+                    ..Default::default()
+                };
+                if let Some(add_noise) = &adv.add_noise {
+                    sampler.add_noise = add_noise == "enable";
+                }
+                advanced = Some(adv);
+            }
+            _ => {
+                return Err(AppError::NodeType {
+                    title: node.meta.title.clone(),
+                    class_type: node.class_type.clone(),
+                    expected: SAMPLER_CLASSES,
+                })
+            }
+        }
+        let id = node.id.clone();
+        match node.inputs {
+            Inputs::KSamplerAdvanced(_) => self.sync_split_steps(wf, &id)?,
+            Inputs::SamplerCustom(_) => {
+                self.rand_custom_inputs(wf, &id, steps, denoise, sampler_name, scheduler)?;
+            }
+            _ => (),
+        }
+        params.sampler = SamplerParams {
+            steps,
+            cfg,
+            denoise,
+            sampler_name: sampler_name.clone(),
+            scheduler: scheduler.clone(),
+            advanced,
+        };
+        Ok(())
+    }
+
+    /// 分步采样: 顺着`latent_image`往下的KSamplerAdvanced都用`id`的采样参数,
+    /// 每段从上一段的`end_at_step`开始
+    fn sync_split_steps(&self, wf: &mut Workflow, id: &str) -> AppResult<()> {
+        let mut prev = wf.by_id(id)?.try_k_sampler_advanced()?.clone();
+        let mut id = id.to_owned();
+        while let Some(link) = wf.downstream(&id).into_iter().find(|l| {
+            l.input == "latent_image"
+                && wf
+                    .by_id(&l.to)
+                    .is_ok_and(|n| n.try_k_sampler_advanced().is_ok())
+        }) {
+            let node = wf.by_id_mut(&link.to)?;
+            let title = node.meta.title.clone();
+            let sampler = node.try_k_sampler_advanced_mut()?;
+            if prev.end_at_step >= prev.steps as u16 {
+                return Err(format!("{title} follows a sampler that runs to the end").into());
+            }
+            sampler.steps = prev.steps;
+            sampler.cfg = prev.cfg;
+            sampler.sampler_name = prev.sampler_name.clone();
+            sampler.scheduler = prev.scheduler.clone();
+            sampler.start_at_step = prev.end_at_step;
+            check_split_steps(&title, sampler)?;
+            trace!(
+                "split step {title}: [{}, {})",
+                sampler.start_at_step,
+                sampler.end_at_step
+            );
+            prev = sampler.clone();
+            id = link.to;
+        }
+        Ok(())
+    }
+
+    /// SamplerCustom上游的KSamplerSelect改采样方法, BasicScheduler改调度/步数/denoise,
+    /// 其它的sigmas结点不改
+    fn rand_custom_inputs(
+        &self,
+        wf: &mut Workflow,
+        id: &str,
+        steps: u8,
+        denoise: f32,
+        sampler_name: &str,
+        scheduler: &str,
+    ) -> AppResult<()> {
+        for link in wf.upstream(id) {
+            let node = wf.by_id_mut(&link.from)?;
+            match (link.input.as_str(), node.class_type.as_str()) {
+                ("sampler", "KSamplerSelect") => {
+                    node.set_input("sampler_name", json!(sampler_name))?;
+                }
+                ("sigmas", "BasicScheduler") => {
+                    node.set_input("scheduler", json!(scheduler))?;
+                    node.set_input("steps", json!(steps))?;
+                    node.set_input("denoise", json!(denoise))?;
+                }
+                (input, class_type) => trace!("SamplerCustom-{id}.{input}: {class_type} unchanged"),
+            }
+        }
         Ok(())
     }
 
-    fn rand_lora(&self, wf: &mut Workflow, ac: &AutoCfg) -> AppResult<()> {
+    fn rand_lora(&self, wf: &mut Workflow, ac: &AutoCfg, params: &mut GenParams) -> AppResult<()> {
         if let Some(alora) = &ac.lora_stack {
-            let lora_stack = wf.get_node_mut(alora.title.as_str())?.lora_stack_mut();
+            let lora_stack = wf
+                .get_node_mut(alora.title.as_str())?
+                .try_lora_stack_mut()?;
             lora_stack.disable_all();
             if alora.switch() {
-                self.rand_lora1(lora_stack, alora);
-                self.rand_lora2(lora_stack, alora);
-                self.rand_lora3(lora_stack, alora);
+                self.rand_lora1(lora_stack, alora, params);
+                self.rand_lora2(lora_stack, alora, params);
+                self.rand_lora3(lora_stack, alora, params);
             }
         }
         if let Some(astacker) = &ac.lora_stacker {
-            let stacker = wf.get_node_mut(astacker.title.as_str())?.lora_stacker_mut();
+            let stacker = wf
+                .get_node_mut(astacker.title.as_str())?
+                .try_lora_stacker_mut()?;
             stacker.disable_all();
             stacker.lora_count = astacker.lora_count;
             for i in 1..=astacker.lora_count {
-                self.rand_loran(i, astacker, stacker);
+                self.rand_loran(i, astacker, stacker, params);
             }
         }
         Ok(())
     }
 
-    fn rand_loran(&self, i: u8, alora: &ALoraStacker, stacker: &mut LoraStacker) {
+    fn rand_loran(
+        &self,
+        i: u8,
+        alora: &ALoraStacker,
+        stacker: &mut LoraStacker,
+        params: &mut GenParams,
+    ) {
         match i {
             1 => {
                 let cfg = rand_lora_cfg(
@@ -490,6 +1042,7 @@ impl Generator {
                     alora.strength_max_1,
                 );
                 stacker.enable(IdxLoRA::LoRA1, &cfg);
+                params.loras.push(cfg);
             }
             2 => {
                 let cfg = rand_lora_cfg(
@@ -498,6 +1051,7 @@ impl Generator {
                     alora.strength_max_2,
                 );
                 stacker.enable(IdxLoRA::LoRA2, &cfg);
+                params.loras.push(cfg);
             }
             3 => {
                 let cfg = rand_lora_cfg(
@@ -506,6 +1060,7 @@ impl Generator {
                     alora.strength_max_3,
                 );
                 stacker.enable(IdxLoRA::LoRA3, &cfg);
+                params.loras.push(cfg);
             }
             4 => {
                 let cfg = rand_lora_cfg(
@@ -514,6 +1069,7 @@ impl Generator {
                     alora.strength_max_4,
                 );
                 stacker.enable(IdxLoRA::LoRA4, &cfg);
+                params.loras.push(cfg);
             }
             _ => {
                 warn!("unhandled lora {i}");
@@ -521,7 +1077,7 @@ impl Generator {
         }
     }
 
-    fn rand_lora1(&self, lora_stack: &mut LoraStack, alora: &ALoraStack) {
+    fn rand_lora1(&self, lora_stack: &mut LoraStack, alora: &ALoraStack, params: &mut GenParams) {
         if !alora.switch_1 {
             return;
         }
@@ -531,9 +1087,10 @@ impl Generator {
             alora.strength_max_1,
         );
         lora_stack.enable(IdxLoRA::LoRA1, &cfg);
+        params.loras.push(cfg);
     }
 
-    fn rand_lora2(&self, lora_stack: &mut LoraStack, alora: &ALoraStack) {
+    fn rand_lora2(&self, lora_stack: &mut LoraStack, alora: &ALoraStack, params: &mut GenParams) {
         if !alora.switch_2 {
             return;
         }
@@ -543,9 +1100,10 @@ impl Generator {
             alora.strength_max_2,
         );
         lora_stack.enable(IdxLoRA::LoRA2, &cfg);
+        params.loras.push(cfg);
     }
 
-    fn rand_lora3(&self, lora_stack: &mut LoraStack, alora: &ALoraStack) {
+    fn rand_lora3(&self, lora_stack: &mut LoraStack, alora: &ALoraStack, params: &mut GenParams) {
         if !alora.switch_3 {
             return;
         }
@@ -555,6 +1113,7 @@ impl Generator {
             alora.strength_max_3,
         );
         lora_stack.enable(IdxLoRA::LoRA3, &cfg);
+        params.loras.push(cfg);
     }
 }
 
@@ -564,6 +1123,67 @@ impl Default for Generator {
     }
 }
 
+/// `rand_sampler`支持的采样器
+const SAMPLER_CLASSES: &str = "KSampler|KSamplerAdvanced|SamplerCustom|KSampler SDXL (Eff.)";
+
+/// 10000表示跑到最后, 否则要在`(start_at_step, steps]`里
+/// 按主采样器复制一个KSampler给hires用, SamplerCustom的采样方法从上游的选择结点取
+fn hires_sampler(wf: &Workflow, title: &str) -> AppResult<KSampler> {
+    let node = wf.get_node(title)?;
+    let sampler = match &node.inputs {
+        Inputs::KSampler(sampler) => sampler.clone(),
+        Inputs::KSamplerAdvanced(sampler) => KSampler {
+            seed: sampler.noise_seed,
+            sampler_name: sampler.sampler_name.clone(),
+            scheduler: sampler.scheduler.clone(),
+            model: sampler.model.clone(),
+            positive: sampler.positive.clone(),
+            negative: sampler.negative.clone(),
+            latent_image: None,
+            cfg: sampler.cfg,
+            denoise: 1.0,
+            steps: sampler.steps,
+        },
+        Inputs::SamplerCustom(sampler) => {
+            let upstream_input = |input: &str, name: &str| -> AppResult<String> {
+                let link = wf.upstream(&node.id).into_iter().find(|l| l.input == input);
+                let from = link.context(format!("hires: {title}.{input} not linked"))?;
+                let inputs = serde_json::to_value(&wf.by_id(&from.from)?.inputs)?;
+                let value = inputs[name].as_str().map(str::to_owned);
+                Ok(value.context(format!("hires: {title}.{input} has no {name}"))?)
+            };
+            KSampler {
+                seed: sampler.noise_seed,
+                sampler_name: upstream_input("sampler", "sampler_name")?,
+                scheduler: upstream_input("sigmas", "scheduler")?,
+                model: sampler.model.clone(),
+                positive: sampler.positive.clone(),
+                negative: sampler.negative.clone(),
+                latent_image: None,
+                cfg: sampler.cfg,
+                denoise: 1.0,
+                steps: 0,
+            }
+        }
+        _ => {
+            return Err(AppError::NodeType {
+                title: title.to_owned(),
+                class_type: node.class_type.clone(),
+                expected: "KSampler|KSamplerAdvanced|SamplerCustom (hires)",
+            })
+        }
+    };
+    Ok(sampler)
+}
+
+fn check_split_steps(title: &str, sampler: &KSamplerAdvanced) -> AppResult<()> {
+    let (start, end, steps) = (sampler.start_at_step, sampler.end_at_step, sampler.steps);
+    if end == 10000 || (start < end && end <= steps as u16) {
+        return Ok(());
+    }
+    Err(format!("{title}: end_at_step {end} not in ({start}, {steps}]").into())
+}
+
 fn rand_lora_cfg(names: &[String], wmodel_min: f32, wmodel_max: f32) -> LoraCfg {
     let model_weight = rand_f32(wmodel_min, wmodel_max);
     LoraCfg {
@@ -604,3 +1224,349 @@ where
         max
     }
 }
+
+#[cfg(test)]
+mod generator_tests {
+    use fixtures::{test_auto_cfg, txt2img, txt2img_sdxl, txt2img_split};
+
+    use super::*;
+    use crate::{ASamplerAdvanced, NODE_IMAGE_SCALESIDE, NODE_SAVE_IMAGE};
+
+    fn test_upscale() -> AImageUpscale {
+        AImageUpscale {
+            title: "UpscaleImage".into(),
+            switch: true,
+            upscale_model: vec!["4x-UltraSharp.pth".into()],
+            mode: vec!["rescale".into()],
+            rescale_factor_min: 2.0,
+            rescale_factor_max: 2.0,
+            resize_width: 1024,
+            resampling_method: vec!["lanczos".into(), "bicubic".into()],
+            supersample: vec!["true".into()],
+            rounding_modulus: 8,
+            hires: None,
+        }
+    }
+
+    #[test]
+    fn switch_should_add_nodes() {
+        let mut ac = AutoCfg::from_toml(test_auto_cfg()).unwrap();
+        ac.image_rembg = Some(AImageRembg {
+            title: "Rembg".into(),
+            switch: true,
+            model_name: "u2net".into(),
+        });
+        ac.image_upscale = Some(test_upscale());
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let params = Generator::new().rand(&mut wf, &ac, 0).unwrap();
+        assert_eq!(params.rembg.as_deref(), Some("u2net"));
+        let upscale = params.upscale.unwrap();
+        assert_eq!(upscale.upscale_model, "4x-UltraSharp.pth");
+        assert_eq!(upscale.rescale_factor, 2.0);
+
+        let rembg = wf.get_node_id("Rembg").unwrap();
+        let load = wf.get_node_id(NODE_LOAD_IMAGE).unwrap();
+        assert_eq!(&wf.upstream(rembg)[0].from, load);
+        let scale = wf.get_node_id(NODE_IMAGE_SCALESIDE).unwrap();
+        assert_eq!(&wf.upstream(scale)[0].from, rembg);
+
+        let upscale = wf.get_node_id("UpscaleImage").unwrap();
+        let decode = wf.get_node_id(NODE_VAE_DECODE).unwrap();
+        assert_eq!(&wf.upstream(upscale)[0].from, decode);
+        let save = wf.get_node_id(NODE_SAVE_IMAGE).unwrap();
+        assert_eq!(&wf.upstream(save)[0].from, upscale);
+        assert!(wf.to_json().is_ok());
+    }
+
+    #[test]
+    fn wrong_title_should_error() {
+        let mut ac = AutoCfg::from_toml(test_auto_cfg()).unwrap();
+        ac.sampler.title = NODE_VAE_DECODE.into();
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let err = Generator::new().rand(&mut wf, &ac, 0).unwrap_err();
+        assert!(matches!(
+            err,
+            AppError::NodeType {
+                expected: SAMPLER_CLASSES,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "VaeDecode(VAEDecode) not KSampler|KSamplerAdvanced|SamplerCustom|KSampler SDXL (Eff.)"
+        );
+    }
+
+    #[test]
+    fn sdxl_should_work() {
+        let mut ac = AutoCfg::from_toml(test_auto_cfg()).unwrap();
+        ac.efficient.ckpt_name = "sd_xl_base_1.0.safetensors".into();
+        ac.efficient.sdxl = Some(ASdxl {
+            refiner_ckpt_name: vec!["sd_xl_refiner_1.0.safetensors".into()],
+            refiner_clip_skip: vec![-2],
+            positive_ascore_min: 6.0,
+            positive_ascore_max: 6.0,
+            negative_ascore_min: 2.5,
+            negative_ascore_max: 2.5,
+            refiner_steps: vec![5],
+        });
+        ac.sampler.steps_min = 30;
+        ac.sampler.steps_max = 30;
+        ac.load_image = None;
+        ac.image_filter = None;
+        ac.ctrlnet_stack = None;
+        ac.lora_stack = None;
+        ac.lora_stacker = None;
+        ac.empty_image = None;
+        ac.image_rembg = None;
+        ac.ip_adapter = None;
+        ac.image_filter_after = None;
+        ac.image_upscale = None;
+        let mut wf = Workflow::from_json(txt2img_sdxl()).unwrap();
+        let params = Generator::new().rand(&mut wf, &ac, 0).unwrap();
+        let sdxl = params.sdxl.unwrap();
+        assert_eq!(sdxl.refiner_ckpt_name, "sd_xl_refiner_1.0.safetensors");
+        assert_eq!(sdxl.negative_ascore, 2.5);
+
+        let loader = wf
+            .get_node(&ac.efficient.title)
+            .unwrap()
+            .efficient_loader_sdxl();
+        assert_eq!(loader.base_ckpt_name, "sd_xl_base_1.0.safetensors");
+        assert_eq!(loader.positive_ascore, 6.0);
+        let sampler = wf.get_node(&ac.sampler.title).unwrap().k_sampler_sdxl();
+        assert_eq!(sampler.steps, 30);
+        assert_eq!(sampler.refine_at_step, 25);
+
+        wf.set_seed(42).unwrap();
+        let sampler = wf.get_node(&ac.sampler.title).unwrap().k_sampler_sdxl();
+        assert_eq!(sampler.noise_seed, 42);
+        assert!(wf.to_json().is_ok());
+
+        // 配置和流程的加载器对不上要报错
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let err = Generator::new().rand(&mut wf, &ac, 0).unwrap_err();
+        assert!(err.to_string().contains("needs EfficientLoaderSdxl"));
+        ac.efficient.sdxl = None;
+        let mut wf = Workflow::from_json(txt2img_sdxl()).unwrap();
+        let err = Generator::new().rand(&mut wf, &ac, 0).unwrap_err();
+        assert!(err.to_string().contains("needs [efficient.sdxl]"));
+    }
+
+    #[test]
+    fn cn_type_should_match_model() {
+        let acfg = ACtrlnet {
+            ctrl_type: vec!["Lineart".into(), "LineartXL".into()],
+            preprocessor: vec![],
+            start_min: 0.0,
+            start_max: 0.0,
+            end_min: 1.0,
+            end_max: 1.0,
+            strength_min: 1.0,
+            strength_max: 1.0,
+            resolution: vec![0],
+        };
+        let gen = Generator::new();
+        for _ in 0..10 {
+            let cfg = gen.rand_cn_cfg(&acfg, (512, 512), true).unwrap();
+            assert!(gen.cns["LineartXL"].model.contains(&cfg.model));
+            let cfg = gen.rand_cn_cfg(&acfg, (512, 512), false).unwrap();
+            assert!(gen.cns["Lineart"].model.contains(&cfg.model));
+        }
+        let acfg = ACtrlnet {
+            ctrl_type: vec!["Lineart".into()],
+            ..acfg
+        };
+        let err = gen.rand_cn_cfg(&acfg, (512, 512), true).unwrap_err();
+        assert_eq!(err.to_string(), r#"no SDXL cn type in ["Lineart"]"#);
+    }
+
+    #[test]
+    fn hires_should_add_nodes() {
+        let mut ac = AutoCfg::from_toml(test_auto_cfg()).unwrap();
+        ac.image_upscale = Some(AImageUpscale {
+            hires: Some(AHires {
+                title: "HiresSampler".into(),
+                steps_min: 10,
+                steps_max: 10,
+                denoise_min: 0.3,
+                denoise_max: 0.5,
+            }),
+            ..test_upscale()
+        });
+        let mut wf = Workflow::from_json(txt2img()).unwrap();
+        let params = Generator::new().rand(&mut wf, &ac, 0).unwrap();
+        let upscale = params.upscale.unwrap();
+        assert!(["lanczos", "bicubic"].contains(&upscale.resampling_method.as_str()));
+        let hires = upscale.hires.unwrap();
+        assert_eq!(hires.steps, 10);
+        assert!((0.3..=0.5).contains(&hires.denoise));
+
+        //VaeDecode -> UpscaleImage -> VaeEncode -> HiresSampler -> VaeDecode -> ImageSave
+        let upscale = wf.get_node_id("UpscaleImage").unwrap();
+        let encode = wf.get_node_id("HiresSamplerVaeEncode").unwrap();
+        let sampler = wf.get_node_id("HiresSampler").unwrap();
+        let decode = wf.get_node_id("HiresSamplerVaeDecode").unwrap();
+        let save = wf.get_node_id(NODE_SAVE_IMAGE).unwrap();
+        assert_eq!(&wf.upstream(encode)[0].from, upscale);
+        assert_eq!(&wf.upstream(save)[0].from, decode);
+        let main = wf.get_node(NODE_KSAMPLER).unwrap().k_sampler();
+        let hires_sampler = wf.by_id(sampler).unwrap().k_sampler();
+        assert_eq!(hires_sampler.model, main.model);
+        assert_eq!(hires_sampler.positive, main.positive);
+        assert_eq!(hires_sampler.latent_image, Some(create_input_id(encode, 0)));
+        assert_eq!(hires_sampler.denoise, hires.denoise);
+        let vae = wf
+            .get_node(NODE_VAE_DECODE)
+            .unwrap()
+            .vae_decode()
+            .vae
+            .clone();
+        assert_eq!(wf.by_id(decode).unwrap().vae_decode().vae, vae);
+        assert!(wf.to_json().is_ok());
+    }
+
+    #[test]
+    fn hires_should_follow_advanced_sampler() {
+        let mut ac = AutoCfg::from_toml(test_auto_cfg()).unwrap();
+        ac.image_upscale = Some(AImageUpscale {
+            hires: Some(AHires {
+                title: "HiresSampler".into(),
+                steps_min: 10,
+                steps_max: 10,
+                denoise_min: 0.4,
+                denoise_max: 0.4,
+            }),
+            ..test_upscale()
+        });
+        ac.sampler.steps_min = 20;
+        ac.sampler.steps_max = 20;
+        ac.sampler.advanced = Some(ASamplerAdvanced {
+            end_at_step: vec![12],
+            ..Default::default()
+        });
+        let mut wf = Workflow::from_json(txt2img_split()).unwrap();
+        Generator::new().rand(&mut wf, &ac, 0).unwrap();
+        let main = wf.get_node(NODE_KSAMPLER).unwrap().k_sampler_advanced();
+        let hires = wf.get_node("HiresSampler").unwrap().k_sampler();
+        assert_eq!(hires.model, main.model);
+        assert_eq!(hires.sampler_name, main.sampler_name);
+        assert_eq!((hires.steps, hires.denoise), (10, 0.4));
+        assert!(wf.to_json().is_ok());
+
+        //SDXL采样器接的是sdxl_tuple, 不能复制成KSampler
+        let wf = Workflow::from_json(txt2img_sdxl()).unwrap();
+        let err = hires_sampler(&wf, NODE_KSAMPLER).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("not KSampler|KSamplerAdvanced|SamplerCustom"));
+    }
+
+    #[test]
+    fn split_step_should_work() {
+        let mut ac = AutoCfg::from_toml(test_auto_cfg()).unwrap();
+        ac.sampler.steps_min = 20;
+        ac.sampler.steps_max = 20;
+        ac.sampler.advanced = Some(ASamplerAdvanced {
+            end_at_step: vec![12],
+            return_with_leftover_noise: vec!["enable".into()],
+            ..Default::default()
+        });
+        let mut wf = Workflow::from_json(txt2img_split()).unwrap();
+        let params = Generator::new().rand(&mut wf, &ac, 0).unwrap();
+        let advanced = params.sampler.advanced.unwrap();
+        assert_eq!(advanced.end_at_step, Some(12));
+        assert_eq!(advanced.add_noise, None);
+        let first = wf.get_node(NODE_KSAMPLER).unwrap().k_sampler_advanced();
+        assert_eq!(first.steps, 20);
+        assert_eq!(first.end_at_step, 12);
+        assert_eq!(first.add_noise, "enable");
+        //后段跟前段同样的采样参数, 从前段停下的地方接着跑
+        let second = wf.get_node("KSamplerEnd").unwrap().k_sampler_advanced();
+        assert_eq!(second.steps, first.steps);
+        assert_eq!(second.cfg, first.cfg);
+        assert_eq!(second.sampler_name, first.sampler_name);
+        assert_eq!(second.scheduler, first.scheduler);
+        assert_eq!(second.start_at_step, first.end_at_step);
+        assert_eq!(second.end_at_step, 10000);
+
+        wf.set_seed(7).unwrap();
+        for title in [NODE_KSAMPLER, "KSamplerEnd"] {
+            let sampler = wf.get_node(title).unwrap().k_sampler_advanced();
+            assert_eq!(sampler.noise_seed, 7);
+        }
+        assert!(wf.to_json().is_ok());
+
+        //前段超出步数, 后段没得跑
+        ac.sampler.advanced.as_mut().unwrap().end_at_step = vec![25];
+        let mut wf = Workflow::from_json(txt2img_split()).unwrap();
+        let err = Generator::new().rand(&mut wf, &ac, 0).unwrap_err();
+        assert_eq!(err.to_string(), "KSampler: end_at_step 25 not in (0, 20]");
+        ac.sampler.advanced.as_mut().unwrap().end_at_step = vec![10000];
+        let mut wf = Workflow::from_json(txt2img_split()).unwrap();
+        let err = Generator::new().rand(&mut wf, &ac, 0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "KSamplerEnd follows a sampler that runs to the end"
+        );
+    }
+
+    #[test]
+    fn sampler_custom_should_work() {
+        let json = json!({
+            "1": {
+                "inputs": {"sampler_name": "euler"},
+                "class_type": "KSamplerSelect",
+                "_meta": {"title": "SamplerSelect"}
+            },
+            "2": {
+                "inputs": {"scheduler": "normal", "steps": 20, "denoise": 1, "model": ["9", 0]},
+                "class_type": "BasicScheduler",
+                "_meta": {"title": "Scheduler"}
+            },
+            "3": {
+                "inputs": {
+                    "add_noise": true, "noise_seed": 0, "cfg": 8,
+                    "sampler": ["1", 0], "sigmas": ["2", 0]
+                },
+                "class_type": "SamplerCustom",
+                "_meta": {"title": "KSampler"}
+            }
+        });
+        let mut wf = Workflow::from_json(&json.to_string()).unwrap();
+        let mut ac = AutoCfg::from_toml(test_auto_cfg()).unwrap();
+        ac.sampler.advanced = Some(ASamplerAdvanced {
+            add_noise: vec!["disable".into()],
+            ..Default::default()
+        });
+        let mut params = GenParams::default();
+        Generator::new()
+            .rand_sampler(&mut wf, &ac, &mut params)
+            .unwrap();
+        let sampler = wf.get_node(NODE_KSAMPLER).unwrap().sampler_custom();
+        assert!(!sampler.add_noise);
+        assert_eq!(sampler.cfg, params.sampler.cfg);
+
+        let input = |title: &str, input: &str| {
+            let node = wf.get_node(title).unwrap();
+            serde_json::to_value(&node.inputs).unwrap()[input].clone()
+        };
+        assert_eq!(
+            input("SamplerSelect", "sampler_name"),
+            json!(params.sampler.sampler_name)
+        );
+        assert_eq!(
+            input("Scheduler", "scheduler"),
+            json!(params.sampler.scheduler)
+        );
+        assert_eq!(input("Scheduler", "steps"), json!(params.sampler.steps));
+        assert_eq!(input("Scheduler", "model"), json!(["9", 0]));
+
+        wf.set_seed(9).unwrap();
+        let sampler = wf.get_node(NODE_KSAMPLER).unwrap().sampler_custom();
+        assert_eq!(sampler.noise_seed, 9);
+        wf.rem_node_with(NODE_KSAMPLER, &RemoveOpts::default())
+            .unwrap();
+        assert!(wf.set_seed(9).is_err());
+    }
+}
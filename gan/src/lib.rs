@@ -13,9 +13,23 @@ pub use auto_cfg::*;
 mod generator;
 pub use generator::*;
 
+mod output;
+pub use output::*;
+
+mod uploader;
+pub use uploader::*;
+
+mod preflight;
+pub use preflight::*;
+
 mod app;
 pub use app::*;
 
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+#[cfg(any(test, feature = "mock"))]
+pub use mock::*;
+
 pub fn rand_element<T>(v: &[T]) -> &T {
     debug_assert!(!v.is_empty());
     if v.len() == 1 {
@@ -1,4 +1,4 @@
-use gan::{App, AppArgs, AppResult};
+use gan::{App, AppArgs, AppCmd, AppResult};
 
 use tracing::trace;
 
@@ -7,5 +7,38 @@ async fn main() -> AppResult<()> {
     tracing_subscriber::fmt::init();
     let args = AppArgs::default();
     trace!("{args:?}");
-    App::new(&args.cfg, args.clean)?.run().await
+    let app = App::new(&args.cfg, args.clean)?;
+    match args.cmd {
+        Some(AppCmd::Check { object_info, save }) => {
+            app.check(object_info.as_deref(), save.as_deref()).await
+        }
+        Some(AppCmd::Convert {
+            ui,
+            out,
+            object_info,
+        }) => app.convert(&ui, &out, object_info.as_deref()).await,
+        Some(AppCmd::Diff {
+            old,
+            new,
+            object_info,
+        }) => {
+            let diff = app.diff(&old, &new, object_info.as_deref()).await?;
+            println!("{diff}");
+            Ok(())
+        }
+        Some(AppCmd::Graph {
+            workflow,
+            format,
+            out,
+            object_info,
+        }) => {
+            let graph = app.graph(&workflow, format, object_info.as_deref()).await?;
+            match out {
+                Some(out) => std::fs::write(out, graph)?,
+                None => print!("{graph}"),
+            }
+            Ok(())
+        }
+        None => app.run().await,
+    }
 }
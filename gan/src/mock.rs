@@ -0,0 +1,578 @@
+//! 进程内的comfy模拟服务器, 用于没有GPU的集成测试
+//!
+//! 收到的prompt按顺序"执行": 依次推送`execution_start`,`executing`,`progress`,
+//! `executed`,`execution_success`及`status`消息, 并记录到`/history`
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use axum::{
+    body::Bytes,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Multipart, Path, Query, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, Notify},
+    task::JoinHandle,
+};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::AppResult;
+
+/// KSampler推送的progress步数
+const MOCK_STEPS: u32 = 3;
+
+/// 推给ws连接的消息
+#[derive(Debug, Clone)]
+enum MockEvent {
+    /// (目标client_id, None为广播), 消息json
+    Msg(Option<String>, String),
+    /// 断开所有ws连接
+    Close,
+}
+
+/// 模拟服务器收到的prompt
+#[derive(Debug, Clone)]
+pub struct MockPrompt {
+    pub prompt_id: String,
+    pub number: i64,
+    pub prompt: Value,
+    pub client_id: String,
+    pub extra_data: Value,
+}
+
+#[derive(Default)]
+struct MockState {
+    /// 收到过的所有prompt
+    received: Mutex<Vec<MockPrompt>>,
+    /// 等待执行的prompt
+    queue: Mutex<VecDeque<MockPrompt>>,
+    /// 正在执行的prompt
+    running: Mutex<Option<MockPrompt>>,
+    history: Mutex<HashMap<String, Value>>,
+    /// 上传的文件名 => 内容
+    uploads: Mutex<HashMap<String, Vec<u8>>>,
+    object_info: Mutex<Value>,
+    number: AtomicI64,
+    /// 暂停执行, prompt留在队列里
+    paused: AtomicBool,
+    /// 下一个prompt执行失败
+    fail_next: AtomicBool,
+    /// 下一次`/prompt`校验失败
+    reject_next: AtomicBool,
+    /// 下一次`/prompt`服务器出错
+    error_next: AtomicBool,
+    interrupted: AtomicBool,
+    events: Option<broadcast::Sender<MockEvent>>,
+    wakeup: Notify,
+}
+
+impl MockState {
+    fn send(&self, target: Option<&str>, typ: &str, data: Value) {
+        let msg = json!({"type": typ, "data": data}).to_string();
+        if let Some(events) = &self.events {
+            let _ = events.send(MockEvent::Msg(target.map(|s| s.to_owned()), msg));
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.queue.lock().unwrap().len() + self.running.lock().unwrap().is_some() as usize
+    }
+
+    fn status(&self) -> Value {
+        json!({"status": {"exec_info": {"queue_remaining": self.remaining()}}})
+    }
+
+    fn is_running(&self, p: &MockPrompt) -> bool {
+        let running = self.running.lock().unwrap();
+        running.as_ref().is_some_and(|r| r.prompt_id == p.prompt_id)
+    }
+
+    fn queue_item(p: &MockPrompt) -> Value {
+        json!([p.number, p.prompt_id, p.prompt, p.extra_data, []])
+    }
+}
+
+/// 进程内comfy服务器, drop时停止
+pub struct MockComfy {
+    addr: SocketAddr,
+    state: Arc<MockState>,
+    server: JoinHandle<()>,
+    worker: JoinHandle<()>,
+}
+
+impl MockComfy {
+    /// 监听127.0.0.1随机端口
+    pub async fn start() -> AppResult<Self> {
+        let (events, _) = broadcast::channel(1024);
+        let state = Arc::new(MockState {
+            object_info: Mutex::new(serde_json::from_str(fixtures::object_info())?),
+            events: Some(events),
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route("/ws", get(ws_handler))
+            .route("/prompt", post(prompt_handler))
+            .route("/queue", get(queue_handler).post(queue_delete_handler))
+            .route("/history", get(history_handler))
+            .route("/history/:prompt_id", get(history_of_handler))
+            .route("/view", get(view_handler))
+            .route("/upload/image", post(upload_handler))
+            .route("/object_info", get(object_info_handler))
+            .route("/object_info/:class_type", get(object_info_of_handler))
+            .route("/system_stats", get(system_stats_handler))
+            .route("/interrupt", post(interrupt_handler))
+            .with_state(state.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        let worker = tokio::spawn(worker(state.clone()));
+        info!("mock comfy on {addr}");
+        Ok(Self {
+            addr,
+            state,
+            server,
+            worker,
+        })
+    }
+
+    /// "host:port", 同`AppCfg.comfy_api`
+    pub fn host(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// 收到过的所有prompt
+    pub fn received(&self) -> Vec<MockPrompt> {
+        self.state.received.lock().unwrap().clone()
+    }
+
+    /// 上传过的文件名
+    pub fn uploads(&self) -> Vec<String> {
+        self.state.uploads.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// 默认为`fixtures::object_info`
+    pub fn set_object_info(&self, object_info: Value) {
+        *self.state.object_info.lock().unwrap() = object_info;
+    }
+
+    /// 暂停|恢复执行, 暂停时第1个prompt停在执行中, 其它留在队列里
+    pub fn pause(&self, paused: bool) {
+        self.state.paused.store(paused, Ordering::Relaxed);
+        self.state.wakeup.notify_one();
+    }
+
+    /// 下一个执行的prompt报`execution_error`
+    pub fn fail_next(&self) {
+        self.state.fail_next.store(true, Ordering::Relaxed);
+    }
+
+    /// 下一次`/prompt`返回400
+    pub fn reject_next(&self) {
+        self.state.reject_next.store(true, Ordering::Relaxed);
+    }
+
+    /// 下一次`/prompt`返回500
+    pub fn error_next(&self) {
+        self.state.error_next.store(true, Ordering::Relaxed);
+    }
+
+    /// 是否有prompt被`/interrupt`中断过
+    pub fn interrupted(&self) -> bool {
+        self.state.interrupted.load(Ordering::Relaxed)
+    }
+
+    /// 正在执行的prompt_id
+    pub fn running(&self) -> Option<String> {
+        let running = self.state.running.lock().unwrap();
+        running.as_ref().map(|p| p.prompt_id.clone())
+    }
+
+    /// 队列中等待的prompt_id
+    pub fn pending(&self) -> Vec<String> {
+        let queue = self.state.queue.lock().unwrap();
+        queue.iter().map(|p| p.prompt_id.clone()).collect()
+    }
+
+    /// 模拟服务器重启: 清空队列和历史, 断开所有ws
+    pub fn restart(&self) {
+        self.state.queue.lock().unwrap().clear();
+        self.state.running.lock().unwrap().take();
+        self.state.history.lock().unwrap().clear();
+        self.state.wakeup.notify_one();
+        self.disconnect();
+    }
+
+    /// 模拟网络断开: 断开所有ws, 队列和历史保留
+    pub fn disconnect(&self) {
+        if let Some(events) = &self.state.events {
+            let _ = events.send(MockEvent::Close);
+        }
+    }
+}
+
+impl Drop for MockComfy {
+    fn drop(&mut self) {
+        self.server.abort();
+        self.worker.abort();
+    }
+}
+
+/// 按顺序执行队列里的prompt
+async fn worker(state: Arc<MockState>) {
+    loop {
+        let next = state.queue.lock().unwrap().pop_front();
+        let Some(p) = next else {
+            state.wakeup.notified().await;
+            continue;
+        };
+        *state.running.lock().unwrap() = Some(p.clone());
+        //暂停时停在正在执行, 等恢复|中断|重启
+        while state.paused.load(Ordering::Relaxed) && state.is_running(&p) {
+            state.wakeup.notified().await;
+        }
+        if !state.is_running(&p) {
+            continue;
+        }
+        execute(&state, &p).await;
+        *state.running.lock().unwrap() = None;
+        state.send(None, "status", state.status());
+    }
+}
+
+async fn execute(state: &MockState, p: &MockPrompt) {
+    let (id, client) = (p.prompt_id.as_str(), Some(p.client_id.as_str()));
+    state.send(client, "execution_start", json!({"prompt_id": id}));
+    state.send(
+        client,
+        "execution_cached",
+        json!({"prompt_id": id, "nodes": []}),
+    );
+    let mut node_ids: Vec<&String> = p
+        .prompt
+        .as_object()
+        .map(|m| m.keys().collect())
+        .unwrap_or_default();
+    node_ids.sort();
+    let mut outputs = serde_json::Map::new();
+    for node_id in node_ids {
+        let node = &p.prompt[node_id];
+        let class_type = node["class_type"].as_str().unwrap_or_default();
+        if state.fail_next.swap(false, Ordering::Relaxed) {
+            state.send(
+                client,
+                "execution_error",
+                json!({
+                    "prompt_id": id, "node_id": node_id, "node_type": class_type,
+                    "executed": [], "exception_message": "mock error",
+                    "exception_type": "RuntimeError", "traceback": []
+                }),
+            );
+            record_history(state, p, outputs, "error");
+            return;
+        }
+        state.send(
+            client,
+            "executing",
+            json!({"node": node_id, "prompt_id": id}),
+        );
+        if class_type.starts_with("KSampler") || class_type == "SamplerCustom" {
+            for value in 1..=MOCK_STEPS {
+                state.send(
+                    client,
+                    "progress",
+                    json!({"value": value, "max": MOCK_STEPS, "prompt_id": id, "node": node_id}),
+                );
+            }
+        }
+        if matches!(class_type, "Image Save" | "SaveImage") {
+            let inputs = &node["inputs"];
+            let prefix = inputs["filename_prefix"].as_str().unwrap_or("ComfyUI");
+            let output = json!({
+                "images": [{
+                    "filename": format!("{prefix}_{:05}_.png", p.number),
+                    "subfolder": inputs["output_path"].as_str().unwrap_or_default(),
+                    "type": "output"
+                }]
+            });
+            state.send(
+                client,
+                "executed",
+                json!({"node": node_id, "output": output, "prompt_id": id}),
+            );
+            outputs.insert(node_id.clone(), output);
+        }
+        tokio::task::yield_now().await;
+    }
+    record_history(state, p, outputs, "success");
+    state.send(client, "executing", json!({"node": null, "prompt_id": id}));
+    state.send(client, "execution_success", json!({"prompt_id": id}));
+}
+
+fn record_history(
+    state: &MockState,
+    p: &MockPrompt,
+    outputs: serde_json::Map<String, Value>,
+    status: &str,
+) {
+    let item = json!({
+        "prompt": MockState::queue_item(p),
+        "outputs": outputs,
+        "status": {"status_str": status, "completed": status == "success", "messages": []}
+    });
+    state
+        .history
+        .lock()
+        .unwrap()
+        .insert(p.prompt_id.clone(), item);
+}
+
+#[derive(Deserialize)]
+struct WsQuery {
+    #[serde(rename = "clientId")]
+    client_id: Option<String>,
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Query(q): Query<WsQuery>,
+    State(state): State<Arc<MockState>>,
+) -> Response {
+    let client_id = q.client_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    ws.on_upgrade(move |socket| ws_session(socket, client_id, state))
+}
+
+async fn ws_session(mut socket: WebSocket, client_id: String, state: Arc<MockState>) {
+    let Some(events) = &state.events else {
+        return;
+    };
+    let mut rx = events.subscribe();
+    let mut status = state.status();
+    status["sid"] = json!(client_id);
+    let hello = json!({"type": "status", "data": status}).to_string();
+    if socket.send(Message::Text(hello)).await.is_err() {
+        return;
+    }
+    debug!("mock ws {client_id} connected");
+    loop {
+        tokio::select! {
+            ev = rx.recv() => match ev {
+                Ok(MockEvent::Msg(target, msg)) => {
+                    if target.is_some_and(|t| t != client_id) {
+                        continue;
+                    }
+                    if socket.send(Message::Text(msg)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(MockEvent::Close) | Err(_) => {
+                    let _ = socket.send(Message::Close(None)).await;
+                    return;
+                }
+            },
+            msg = socket.recv() => {
+                if !matches!(msg, Some(Ok(_))) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PromptReq {
+    prompt: Value,
+    #[serde(default)]
+    client_id: String,
+    #[serde(default)]
+    extra_data: Value,
+}
+
+async fn prompt_handler(
+    State(state): State<Arc<MockState>>,
+    Json(req): Json<PromptReq>,
+) -> Response {
+    if state.error_next.swap(false, Ordering::Relaxed) {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    if state.reject_next.swap(false, Ordering::Relaxed) {
+        let rsp = json!({
+            "error": {
+                "type": "prompt_outputs_failed_validation",
+                "message": "Prompt outputs failed validation",
+                "details": "",
+                "extra_info": {}
+            },
+            "node_errors": {}
+        });
+        return (StatusCode::BAD_REQUEST, Json(rsp)).into_response();
+    }
+    let mut extra_data = req.extra_data;
+    if !extra_data.is_object() {
+        extra_data = json!({});
+    }
+    extra_data["client_id"] = json!(req.client_id);
+    let p = MockPrompt {
+        prompt_id: Uuid::new_v4().to_string(),
+        number: state.number.fetch_add(1, Ordering::Relaxed),
+        prompt: req.prompt,
+        client_id: req.client_id,
+        extra_data,
+    };
+    let rsp = json!({"prompt_id": p.prompt_id, "number": p.number, "node_errors": {}});
+    state.received.lock().unwrap().push(p.clone());
+    state.queue.lock().unwrap().push_back(p);
+    state.send(None, "status", state.status());
+    state.wakeup.notify_one();
+    Json(rsp).into_response()
+}
+
+async fn queue_handler(State(state): State<Arc<MockState>>) -> Json<Value> {
+    let running: Vec<Value> = state
+        .running
+        .lock()
+        .unwrap()
+        .iter()
+        .map(MockState::queue_item)
+        .collect();
+    let pending: Vec<Value> = state
+        .queue
+        .lock()
+        .unwrap()
+        .iter()
+        .map(MockState::queue_item)
+        .collect();
+    Json(json!({"queue_running": running, "queue_pending": pending}))
+}
+
+#[derive(Deserialize)]
+struct QueueReq {
+    #[serde(default)]
+    delete: Vec<String>,
+    #[serde(default)]
+    clear: bool,
+}
+
+async fn queue_delete_handler(
+    State(state): State<Arc<MockState>>,
+    Json(req): Json<QueueReq>,
+) -> StatusCode {
+    let mut queue = state.queue.lock().unwrap();
+    if req.clear {
+        queue.clear();
+    } else {
+        queue.retain(|p| !req.delete.contains(&p.prompt_id));
+    }
+    StatusCode::OK
+}
+
+async fn history_handler(State(state): State<Arc<MockState>>) -> Json<Value> {
+    Json(json!(*state.history.lock().unwrap()))
+}
+
+async fn history_of_handler(
+    State(state): State<Arc<MockState>>,
+    Path(prompt_id): Path<String>,
+) -> Json<Value> {
+    let history = state.history.lock().unwrap();
+    match history.get(&prompt_id) {
+        Some(item) => Json(json!({ prompt_id: item })),
+        None => Json(json!({})),
+    }
+}
+
+#[derive(Deserialize)]
+struct ViewQuery {
+    filename: String,
+}
+
+/// 图片内容就是文件名, 方便校验
+async fn view_handler(Query(q): Query<ViewQuery>) -> Bytes {
+    Bytes::from(q.filename.into_bytes())
+}
+
+async fn upload_handler(State(state): State<Arc<MockState>>, mut multipart: Multipart) -> Response {
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() != Some("image") {
+            continue;
+        }
+        let name = field.file_name().unwrap_or("upload.png").to_owned();
+        let Ok(data) = field.bytes().await else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        state
+            .uploads
+            .lock()
+            .unwrap()
+            .insert(name.clone(), data.to_vec());
+        return Json(json!({"name": name, "subfolder": "", "type": "input"})).into_response();
+    }
+    StatusCode::BAD_REQUEST.into_response()
+}
+
+async fn object_info_handler(State(state): State<Arc<MockState>>) -> Json<Value> {
+    Json(state.object_info.lock().unwrap().clone())
+}
+
+async fn object_info_of_handler(
+    State(state): State<Arc<MockState>>,
+    Path(class_type): Path<String>,
+) -> Json<Value> {
+    let object_info = state.object_info.lock().unwrap();
+    match object_info.get(&class_type) {
+        Some(info) => Json(json!({ class_type: info })),
+        None => Json(json!({})),
+    }
+}
+
+async fn system_stats_handler() -> Json<Value> {
+    Json(json!({
+        "system": {"os": "posix", "python_version": "3.10.12", "embedded_python": false},
+        "devices": [{
+            "name": "mock", "type": "cpu", "index": null,
+            "vram_total": 0, "vram_free": 0, "torch_vram_total": 0, "torch_vram_free": 0
+        }]
+    }))
+}
+
+/// 只能中断暂停中的prompt, 其它的执行太快
+/// 同comfy, 带`prompt_id`时只在它正在跑时中断, 不带时中断当前的
+async fn interrupt_handler(
+    State(state): State<Arc<MockState>>,
+    Json(req): Json<Value>,
+) -> StatusCode {
+    let running = {
+        let mut running = state.running.lock().unwrap();
+        match req.get("prompt_id").and_then(Value::as_str) {
+            Some(id) if running.as_ref().is_some_and(|p| p.prompt_id != id) => None,
+            _ => running.take(),
+        }
+    };
+    if let Some(p) = running {
+        state.interrupted.store(true, Ordering::Relaxed);
+        let data = json!({
+            "prompt_id": p.prompt_id, "node_id": "", "node_type": "", "executed": []
+        });
+        state.send(Some(&p.client_id), "execution_interrupted", data);
+        state.wakeup.notify_one();
+    }
+    StatusCode::OK
+}
@@ -0,0 +1,105 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{AppResult, GenRecord, ImageRef};
+
+/// 本地出图目录, 按comfy上的subfolder存放, 同名`.json`记录出图参数.
+/// 各服务器的文件名都从`prefix_00001_.png`编号, 文件名前加prompt_id免得互相覆盖
+pub struct OutputStore {
+    dir: PathBuf,
+}
+
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    prompt_id: &'a str,
+    image: &'a ImageRef,
+    #[serde(flatten)]
+    record: &'a GenRecord,
+}
+
+impl OutputStore {
+    pub fn new(dir: &str) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// 保存图片及参数, 返回图片路径
+    pub fn save(
+        &self,
+        prompt_id: &str,
+        image: &ImageRef,
+        data: &[u8],
+        record: &GenRecord,
+    ) -> AppResult<PathBuf> {
+        let dir = self.dir.join(safe_path(&image.subfolder));
+        fs::create_dir_all(&dir)?;
+        let filename = format!("{prompt_id}_{}", image.filename);
+        let path = dir.join(safe_path(&filename));
+        fs::write(&path, data)?;
+        let sidecar = Sidecar {
+            prompt_id,
+            image,
+            record,
+        };
+        fs::write(
+            path.with_extension("json"),
+            serde_json::to_string_pretty(&sidecar)?,
+        )?;
+        debug!("saved {}", path.display());
+        Ok(path)
+    }
+}
+
+/// 服务器给的路径只保留普通部分, 不能跳出输出目录
+fn safe_path(p: &str) -> PathBuf {
+    Path::new(p)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::*;
+
+    #[test]
+    fn safe_path_should_work() {
+        assert_eq!(safe_path("a/b"), PathBuf::from("a/b"));
+        assert_eq!(safe_path("../../etc"), PathBuf::from("etc"));
+        assert_eq!(safe_path("/abs/x.png"), PathBuf::from("abs/x.png"));
+        assert_eq!(safe_path(""), PathBuf::new());
+    }
+
+    #[test]
+    fn save_should_work() {
+        let dir = std::env::temp_dir().join(format!("gan_output_{}", std::process::id()));
+        let store = OutputStore::new(dir.to_str().unwrap());
+        let image = ImageRef {
+            filename: "a_00001_.png".into(),
+            subfolder: "sketch".into(),
+            typ: "output".into(),
+        };
+        let record = GenRecord {
+            workflow: "文生图api.json".into(),
+            seed: 42,
+            ..Default::default()
+        };
+        let path = store.save("p1", &image, b"png", &record).unwrap();
+        assert_eq!(path, dir.join("sketch/p1_a_00001_.png"));
+        assert_eq!(fs::read(&path).unwrap(), b"png");
+        let sidecar: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path.with_extension("json")).unwrap())
+                .unwrap();
+        assert_eq!(sidecar["prompt_id"], "p1");
+        assert_eq!(sidecar["seed"], 42);
+        assert_eq!(sidecar["image"]["subfolder"], "sketch");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
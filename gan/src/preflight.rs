@@ -0,0 +1,466 @@
+use std::{collections::HashMap, fmt, fs};
+
+use fixtures::control_nets;
+
+use crate::{
+    comfy_class_map, comfy_preprocessor, AppResult, AutoCfg, Comfy, Ctrlnet, IdxControlNet,
+    ObjectInfo, Workflow,
+};
+
+/// 没有专用结点的preprocessor都走`AIO_Preprocessor`
+const AIO_PREPROCESSOR: &str = "AIO_Preprocessor";
+const REMBG: &str = "Image Remove Background (rembg)";
+const UPSCALE: &str = "CR Upscale Image";
+
+/// 出图前用`/object_info`检查`AutoCfg`里所有可能被随机选中的值
+pub struct Preflight {
+    info: ObjectInfo,
+    cns: HashMap<String, Ctrlnet>,
+}
+
+/// 一个不可用的取值
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreflightIssue {
+    /// `AutoCfg`配置段, 如`efficient`
+    pub section: String,
+    pub field: String,
+    pub value: String,
+    pub reason: String,
+}
+
+/// 检查结果, 按配置段汇总
+#[derive(Debug, Default)]
+pub struct PreflightReport {
+    pub issues: Vec<PreflightIssue>,
+}
+
+impl PreflightReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn section(&self, section: &str) -> Vec<&PreflightIssue> {
+        self.issues
+            .iter()
+            .filter(|i| i.section == section)
+            .collect()
+    }
+
+    fn push(&mut self, section: &str, field: &str, value: &str, reason: String) {
+        let issue = PreflightIssue {
+            section: section.to_owned(),
+            field: field.to_owned(),
+            value: value.to_owned(),
+            reason,
+        };
+        //多个流程同一结点的问题只报一次
+        if !self.issues.contains(&issue) {
+            self.issues.push(issue);
+        }
+    }
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sections: Vec<&str> = Vec::new();
+        for issue in self.issues.iter() {
+            if !sections.contains(&issue.section.as_str()) {
+                sections.push(&issue.section);
+            }
+        }
+        for section in sections {
+            writeln!(f, "[{section}]")?;
+            for issue in self.section(section) {
+                writeln!(f, "  {}={:?}: {}", issue.field, issue.value, issue.reason)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Preflight {
+    pub fn new(info: ObjectInfo) -> Self {
+        Self {
+            info,
+            cns: serde_json::from_str(control_nets()).unwrap(),
+        }
+    }
+
+    pub fn from_json(json: &str) -> AppResult<Self> {
+        Ok(Self::new(serde_json::from_str(json)?))
+    }
+
+    /// 离线用缓存的`/object_info`
+    pub fn from_file(json_file: &str) -> AppResult<Self> {
+        Self::from_json(fs::read_to_string(json_file)?.as_str())
+    }
+
+    pub async fn from_api(api: &Comfy) -> AppResult<Self> {
+        Ok(Self::new(api.object_info().await?))
+    }
+
+    pub fn object_info(&self) -> &ObjectInfo {
+        &self.info
+    }
+
+    pub fn into_object_info(self) -> ObjectInfo {
+        self.info
+    }
+
+    pub fn check(&self, ac: &AutoCfg) -> PreflightReport {
+        let mut report = PreflightReport::default();
+        let mut wfs = Vec::new();
+        for wf_file in ac.workflows.iter() {
+            match Workflow::load(wf_file, &self.info) {
+                Ok(mut wf) => {
+                    for fragment in ac.fragments.iter() {
+                        if let Err(e) = wf.attach_cfg(fragment) {
+                            report.push("fragments", &fragment.file, wf_file, e.to_string());
+                        }
+                    }
+                    self.check_workflow(wf_file, &wf, &mut report);
+                    wfs.push(wf);
+                }
+                Err(e) => report.push("workflows", "workflows", wf_file, e.to_string()),
+            }
+        }
+        let mut ctx = Ctx {
+            pf: self,
+            wfs: &wfs,
+            report: &mut report,
+        };
+
+        let ec = &ac.efficient;
+        let ckpt_input = match ec.sdxl {
+            Some(_) => "base_ckpt_name",
+            None => "ckpt_name",
+        };
+        ctx.check(
+            "efficient",
+            &ec.title,
+            "ckpt_name",
+            ckpt_input,
+            &ec.ckpt_name,
+        );
+        if let Some(asdxl) = &ec.sdxl {
+            for name in asdxl.refiner_ckpt_name.iter() {
+                let field = "sdxl.refiner_ckpt_name";
+                ctx.check("efficient", &ec.title, field, "refiner_ckpt_name", name);
+            }
+        }
+        for vae_name in ec.vae_name.iter() {
+            ctx.check("efficient", &ec.title, "vae_name", "vae_name", vae_name);
+        }
+        ctx.check(
+            "efficient",
+            &ec.title,
+            "weight_interpretation",
+            "weight_interpretation",
+            &ec.weight_interpretation,
+        );
+
+        let sc = &ac.sampler;
+        for sampler_name in sc.sampler_name.iter() {
+            ctx.check(
+                "sampler",
+                &sc.title,
+                "sampler_name",
+                "sampler_name",
+                sampler_name,
+            );
+        }
+        for scheduler in sc.scheduler.iter() {
+            ctx.check("sampler", &sc.title, "scheduler", "scheduler", scheduler);
+        }
+        if let Some(adv) = &sc.advanced {
+            //SamplerCustom的add_noise是布尔, 不检查
+            let choices = [
+                ("add_noise", &adv.add_noise),
+                (
+                    "return_with_leftover_noise",
+                    &adv.return_with_leftover_noise,
+                ),
+            ];
+            for (input, values) in choices {
+                let field = format!("advanced.{input}");
+                for value in values.iter() {
+                    ctx.check("sampler", &sc.title, &field, input, value);
+                }
+            }
+        }
+
+        if let Some(alora) = &ac.lora_stack {
+            let loras = [
+                (alora.switch_1, &alora.model_name_1),
+                (alora.switch_2, &alora.model_name_2),
+                (alora.switch_3, &alora.model_name_3),
+            ];
+            for (i, (_, names)) in loras.iter().enumerate().filter(|(_, l)| l.0) {
+                let (field, input) = (
+                    format!("model_name_{}", i + 1),
+                    format!("lora_name_{}", i + 1),
+                );
+                for name in names.iter() {
+                    ctx.check("lora_stack", &alora.title, &field, &input, name);
+                }
+            }
+        }
+
+        if let Some(astacker) = &ac.lora_stacker {
+            let loras = [
+                &astacker.model_name_1,
+                &astacker.model_name_2,
+                &astacker.model_name_3,
+                &astacker.model_name_4,
+            ];
+            for (i, names) in loras.iter().take(astacker.lora_count as usize).enumerate() {
+                let (field, input) = (
+                    format!("model_name_{}", i + 1),
+                    format!("lora_name_{}", i + 1),
+                );
+                for name in names.iter() {
+                    ctx.check("lora_stacker", &astacker.title, &field, &input, name);
+                }
+            }
+        }
+
+        if let Some(acn) = &ac.ctrlnet_stack {
+            let idxs = [
+                IdxControlNet::ControlNet1,
+                IdxControlNet::ControlNet2,
+                IdxControlNet::ControlNet3,
+            ];
+            for (i, idx) in idxs.iter().enumerate() {
+                let Some(acfg) = acn.cfg(idx) else {
+                    continue;
+                };
+                let n = i + 1;
+                let (type_field, input) = (format!("ctrl_type_{n}"), format!("controlnet_{n}"));
+                for ctrl_type in acfg.ctrl_type.iter() {
+                    let Some(cn) = self.cns.get(ctrl_type) else {
+                        let reason = "not in control_nets.json".to_owned();
+                        ctx.report
+                            .push("ctrlnet_stack", &type_field, ctrl_type, reason);
+                        continue;
+                    };
+                    if cn.sdxl != ec.sdxl.is_some() {
+                        let (cn_model, ckpt) = match cn.sdxl {
+                            true => ("SDXL", "SD1.5"),
+                            false => ("SD1.5", "SDXL"),
+                        };
+                        let reason = format!("{cn_model} cn with {ckpt} ckpt, never picked");
+                        ctx.report
+                            .push("ctrlnet_stack", &type_field, ctrl_type, reason);
+                        continue;
+                    }
+                    for model in cn.model.iter() {
+                        ctx.check("ctrlnet_stack", &acn.title, &type_field, &input, model);
+                    }
+                    let processors = if acfg.preprocessor.is_empty() {
+                        &cn.preprocessor
+                    } else {
+                        &acfg.preprocessor
+                    };
+                    for processor in processors.iter() {
+                        ctx.check_preprocessor(&format!("processor_{n}"), processor);
+                    }
+                }
+            }
+        }
+
+        //去背景和放大结点流程里没有时会自动加, 按class_type检查
+        if let Some(arembg) = ac.image_rembg.as_ref().filter(|a| a.switch) {
+            let name = &arembg.model_name;
+            ctx.check_class("image_rembg", REMBG, "model_name", "model_name", name);
+        }
+        if let Some(aupscale) = ac.image_upscale.as_ref().filter(|a| a.switch) {
+            let section = "image_upscale";
+            let choices = [
+                ("upscale_model", &aupscale.upscale_model),
+                ("mode", &aupscale.mode),
+                ("resampling_method", &aupscale.resampling_method),
+                ("supersample", &aupscale.supersample),
+            ];
+            for (input, values) in choices {
+                for value in values.iter() {
+                    ctx.check_class(section, UPSCALE, input, input, value);
+                }
+            }
+        }
+        if let Some(atagger) = ac.tagger.as_ref().filter(|a| a.switch) {
+            ctx.check("tagger", &atagger.title, "model", "model", &atagger.model);
+        }
+
+        report
+    }
+
+    /// 流程里的结点服务器都要有
+    fn check_workflow(&self, wf_file: &str, wf: &Workflow, report: &mut PreflightReport) {
+        for node in wf.nodes() {
+            if !self.info.contains_key(&node.class_type) {
+                let reason = format!("node {} not installed", node.class_type);
+                report.push("workflows", wf_file, &node.meta.title, reason);
+            }
+        }
+    }
+}
+
+struct Ctx<'a> {
+    pf: &'a Preflight,
+    wfs: &'a [Workflow],
+    report: &'a mut PreflightReport,
+}
+
+impl Ctx<'_> {
+    /// 按标题找到各流程里的结点, 检查`value`在结点输入`input`的可选值里
+    fn check(&mut self, section: &str, title: &str, field: &str, input: &str, value: &str) {
+        let mut found = false;
+        for wf in self.wfs.iter() {
+            let Ok(node) = wf.get_node(title) else {
+                continue;
+            };
+            found = true;
+            self.check_class(section, &node.class_type, field, input, value);
+        }
+        if !found && !self.wfs.is_empty() {
+            let reason = format!("no node titled {title} in workflows");
+            self.report.push(section, "title", title, reason);
+        }
+    }
+
+    fn check_class(
+        &mut self,
+        section: &str,
+        class_type: &str,
+        field: &str,
+        input: &str,
+        value: &str,
+    ) {
+        let Some(info) = self.pf.info.get(class_type) else {
+            let reason = format!("node {class_type} not installed");
+            self.report.push(section, field, value, reason);
+            return;
+        };
+        //不是枚举的输入不检查
+        if let Some(choices) = info.choices(input) {
+            if !choices.contains(&value) {
+                let reason = format!("not in {class_type}.{input}");
+                self.report.push(section, field, value, reason);
+            }
+        }
+    }
+
+    /// 有专用结点的preprocessor要求结点已安装, 其它的要在`AIO_Preprocessor`可选值里
+    fn check_preprocessor(&mut self, field: &str, my_name: &str) {
+        let class_type = comfy_preprocessor(my_name);
+        if comfy_class_map().contains_key(class_type) {
+            if !self.pf.info.contains_key(class_type) {
+                let reason = format!("node {class_type} not installed");
+                self.report.push("ctrlnet_stack", field, my_name, reason);
+            }
+        } else {
+            let section = "ctrlnet_stack";
+            self.check_class(section, AIO_PREPROCESSOR, field, "preprocessor", class_type);
+        }
+    }
+}
+
+#[cfg(test)]
+mod preflight_tests {
+    use super::*;
+
+    fn test_ac() -> AutoCfg {
+        let mut ac = AutoCfg::from_toml(fixtures::test_auto_cfg()).unwrap();
+        let wf = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/data/文生图api.json");
+        ac.workflows = vec![wf.to_owned()];
+        ac
+    }
+
+    #[test]
+    fn preflight_should_work() {
+        let pf = Preflight::from_json(fixtures::object_info()).unwrap();
+        let report = pf.check(&test_ac());
+        assert!(report.is_ok(), "{report}");
+    }
+
+    #[test]
+    fn preflight_should_report_invalid() {
+        let pf = Preflight::from_json(fixtures::object_info()).unwrap();
+        let mut ac = test_ac();
+        ac.efficient.ckpt_name = "typo.safetensors".into();
+        ac.sampler.scheduler.push("karas".into());
+        ac.lora_stacker.as_mut().unwrap().model_name_2 = vec!["missing.safetensors".into()];
+        ac.ctrlnet_stack.as_mut().unwrap().ctrl_type_2 = vec!["Pose".into()];
+        let report = pf.check(&ac);
+        let efficient = report.section("efficient");
+        assert_eq!(efficient.len(), 1);
+        assert_eq!(efficient[0].field, "ckpt_name");
+        assert_eq!(efficient[0].reason, "not in Efficient Loader.ckpt_name");
+        assert_eq!(report.section("sampler")[0].value, "karas");
+        assert_eq!(report.section("lora_stacker")[0].field, "model_name_2");
+        assert_eq!(report.section("ctrlnet_stack")[0].value, "Pose");
+        assert_eq!(report.issues.len(), 4);
+        let text = report.to_string();
+        assert!(text.starts_with("[efficient]\n  ckpt_name=\"typo.safetensors\""));
+    }
+
+    #[test]
+    fn preflight_should_report_missing_nodes() {
+        let mut info: ObjectInfo = serde_json::from_str(fixtures::object_info()).unwrap();
+        info.remove("WD14Tagger|pysssss");
+        info.remove("LineArtPreprocessor");
+        let report = Preflight::new(info).check(&test_ac());
+        assert_eq!(report.section("workflows").len(), 2);
+        let cn = report.section("ctrlnet_stack");
+        assert_eq!(cn[0].field, "processor_1");
+        assert_eq!(cn[0].reason, "node LineArtPreprocessor not installed");
+    }
+
+    #[test]
+    fn preflight_should_check_sdxl() {
+        let pf = Preflight::from_json(fixtures::object_info()).unwrap();
+        let mut ac = test_ac();
+        let wf = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/data/SDXL文生图api.json"
+        );
+        ac.workflows = vec![wf.to_owned()];
+        ac.efficient.ckpt_name = "sd_xl_base_1.0.safetensors".into();
+        ac.efficient.sdxl = Some(crate::ASdxl {
+            refiner_ckpt_name: vec!["None".into(), "typo.safetensors".into()],
+            refiner_clip_skip: vec![-2],
+            positive_ascore_min: 6.0,
+            positive_ascore_max: 6.0,
+            negative_ascore_min: 2.0,
+            negative_ascore_max: 2.0,
+            refiner_steps: vec![5],
+        });
+        let report = pf.check(&ac);
+        let efficient = report.section("efficient");
+        assert_eq!(efficient.len(), 1);
+        assert_eq!(efficient[0].value, "typo.safetensors");
+        let cn = report.section("ctrlnet_stack");
+        assert_eq!(cn[0].value, "Lineart");
+        assert_eq!(cn[0].reason, "SD1.5 cn with SDXL ckpt, never picked");
+    }
+
+    #[test]
+    fn preflight_should_check_advanced() {
+        let pf = Preflight::from_json(fixtures::object_info()).unwrap();
+        let mut ac = test_ac();
+        let wf = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/fixtures/data/分步采样api.json"
+        );
+        ac.workflows = vec![wf.to_owned()];
+        ac.sampler.advanced = Some(crate::ASamplerAdvanced {
+            add_noise: vec!["enable".into(), "on".into()],
+            ..Default::default()
+        });
+        let report = pf.check(&ac);
+        let sampler = report.section("sampler");
+        assert_eq!(sampler.len(), 1, "{report}");
+        assert_eq!(sampler[0].field, "advanced.add_noise");
+        assert_eq!(sampler[0].reason, "not in KSamplerAdvanced.add_noise");
+    }
+}
@@ -0,0 +1,90 @@
+use std::{collections::HashMap, fs, path::Path, sync::Mutex};
+
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::{AppResult, Comfy, Inputs, Workflow};
+
+/// 把流程里引用的本地图片上传到comfy, 按内容hash去重
+#[derive(Default)]
+pub struct Uploader {
+    /// host:内容hash => comfy上的文件名
+    uploaded: Mutex<HashMap<String, String>>,
+}
+
+impl Uploader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `LoadImage.image`是本地存在的文件时上传, 并改成上传后的文件名
+    pub async fn upload_inputs(&self, wf: &mut Workflow, api: &Comfy) -> AppResult<()> {
+        for node in wf.nodes_mut() {
+            if let Inputs::LoadImage(load) = &mut node.inputs {
+                let path = Path::new(&load.image);
+                if !path.is_file() {
+                    continue;
+                }
+                let name = self.upload(path, api).await?;
+                debug!("{} {} => {name}", node.meta.title, load.image);
+                load.image = name;
+            }
+        }
+        Ok(())
+    }
+
+    /// 上传单个文件, 已传过相同内容的直接返回之前的文件名
+    pub async fn upload(&self, path: &Path, api: &Comfy) -> AppResult<String> {
+        let data = fs::read(path)?;
+        let hash = content_hash(&data);
+        let key = format!("{}:{hash}", api.host());
+        if let Some(name) = self.uploaded.lock().unwrap().get(&key) {
+            return Ok(name.clone());
+        }
+        let name = upload_name(path, &hash);
+        let uploaded = api.upload_image(&name, data).await?;
+        let name = if uploaded.subfolder.is_empty() {
+            uploaded.name
+        } else {
+            format!("{}/{}", uploaded.subfolder, uploaded.name)
+        };
+        info!("uploaded {} => {name}", path.display());
+        self.uploaded.lock().unwrap().insert(key, name.clone());
+        Ok(name)
+    }
+}
+
+fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// 保留原文件名(`land_`前缀等), 加上hash避免不同内容重名覆盖
+fn upload_name(path: &Path, hash: &str) -> String {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("img");
+    match path.extension().and_then(|s| s.to_str()) {
+        Some(ext) => format!("{stem}_{}.{ext}", &hash[..12]),
+        None => format!("{stem}_{}", &hash[..12]),
+    }
+}
+
+#[cfg(test)]
+mod uploader_tests {
+    use super::*;
+
+    #[test]
+    fn upload_name_should_work() {
+        let hash = content_hash(b"abc");
+        assert_eq!(
+            hash,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            upload_name(Path::new("imgs/land_1.png"), &hash),
+            "land_1_ba7816bf8f01.png"
+        );
+        assert_eq!(upload_name(Path::new("a"), &hash), "a_ba7816bf8f01");
+    }
+}